@@ -0,0 +1,285 @@
+//! Where the collectors get host-wide figures from.
+//!
+//! Two backends produce the same data: [`sysinfo`](native::SysinfoBackend),
+//! used on every platform it supports, and a reader of `/proc` that needs
+//! nothing but file access ([`ProcBackend`](proc::ProcBackend)). sysinfo has
+//! no WASI support and reports zeros there, so builds for `wasm32-wasi` use
+//! the `/proc` reader, which sees the host's `/proc` and `/sys` through the
+//! directories the runtime preopens:
+//!
+//! ```text
+//! wasmtime run --dir /proc --dir /sys metrics_client.wasm
+//! ```
+//!
+//! Collectors that read `/proc` or `/sys` themselves do so through
+//! [`procfs`](crate::procfs), whose roots follow the same configuration.
+
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[cfg(not(target_os = "wasi"))]
+pub mod native;
+pub mod proc;
+
+/// Memory and swap, in bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Memory {
+    pub total: u64,
+    pub available: u64,
+    pub swap_total: u64,
+    pub swap_used: u64,
+}
+
+/// One core, or all of them together.
+#[derive(Debug, Clone, Default)]
+pub struct Cpu {
+    /// The kernel's number for the core, the N of `cpuN`, which skips
+    /// offline cores. `None` for the aggregate, or where it is unknown.
+    pub id: Option<usize>,
+    /// Busy time since the previous refresh, in percent.
+    pub usage: f32,
+    /// Current clock in MHz, 0 when unknown.
+    pub frequency: u64,
+    pub vendor: String,
+    pub brand: String,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+/// A mounted filesystem, with its space in bytes.
+#[derive(Debug, Clone)]
+pub struct Disk {
+    pub device: String,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub total: u64,
+    pub available: u64,
+}
+
+/// Cumulative counters of one network interface.
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Process {
+    pub pid: u32,
+    pub name: String,
+    /// The command line, program first; empty for kernel threads.
+    pub cmd: Vec<String>,
+    /// CPU time since the previous refresh, where 100% is one core.
+    pub cpu_usage: f32,
+    /// Resident memory in bytes.
+    pub memory: u64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    /// Seconds since the Unix epoch.
+    pub start_time: u64,
+}
+
+/// A temperature sensor.
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub label: String,
+    /// Degrees Celsius.
+    pub temperature: f32,
+    pub critical: Option<f32>,
+}
+
+/// The parts of a [`Backend`] a refresh reads again; the others keep what
+/// they last read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Refresh {
+    pub memory: bool,
+    pub cpu: bool,
+    /// Host name, load average, uptime and boot time.
+    pub system: bool,
+    pub disks: bool,
+    pub networks: bool,
+    pub processes: bool,
+    pub components: bool,
+    /// The agent's own process, without the rest of the process list.
+    pub agent: bool,
+}
+
+impl Refresh {
+    pub const EVERYTHING: Refresh = Refresh {
+        memory: true,
+        cpu: true,
+        system: true,
+        disks: true,
+        networks: true,
+        processes: true,
+        components: true,
+        agent: true,
+    };
+
+    /// Everything either `self` or `other` refreshes.
+    pub fn union(self, other: Refresh) -> Refresh {
+        Refresh {
+            memory: self.memory || other.memory,
+            cpu: self.cpu || other.cpu,
+            system: self.system || other.system,
+            disks: self.disks || other.disks,
+            networks: self.networks || other.networks,
+            processes: self.processes || other.processes,
+            components: self.components || other.components,
+            agent: self.agent || other.agent,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Refresh::default()
+    }
+}
+
+/// A source of host-wide figures. Every accessor returns what the last
+/// [`refresh`](Backend::refresh) of its part read.
+pub trait Backend {
+    fn refresh(&mut self, what: Refresh);
+
+    fn host_name(&self) -> Option<String>;
+    fn memory(&self) -> Memory;
+    /// All cores together.
+    fn global_cpu(&self) -> Cpu;
+    /// Each core, in the kernel's order.
+    fn cpus(&self) -> Vec<Cpu>;
+    fn physical_core_count(&self) -> Option<usize>;
+    fn load_average(&self) -> LoadAverage;
+    /// Seconds since boot.
+    fn uptime(&self) -> u64;
+    /// Seconds since the Unix epoch.
+    fn boot_time(&self) -> u64;
+    fn disks(&self) -> Vec<Disk>;
+    fn networks(&self) -> Vec<Network>;
+    fn processes(&self) -> Vec<Process>;
+    fn components(&self) -> Vec<Component>;
+    /// The agent's own process, or `None` if it could not be read.
+    fn agent(&self) -> Option<Process>;
+}
+
+/// Which [`Backend`] the agent reads from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    Sysinfo,
+    Procfs,
+}
+
+impl BackendKind {
+    /// Whether this build includes the backend.
+    pub fn is_available(&self) -> bool {
+        match self {
+            BackendKind::Sysinfo => cfg!(not(target_os = "wasi")),
+            BackendKind::Procfs => true,
+        }
+    }
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        if cfg!(target_os = "wasi") {
+            BackendKind::Procfs
+        } else {
+            BackendKind::Sysinfo
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackendKind::Sysinfo => "sysinfo",
+            BackendKind::Procfs => "procfs",
+        })
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sysinfo" => Ok(BackendKind::Sysinfo),
+            "procfs" => Ok(BackendKind::Procfs),
+            other => Err(format!(
+                "unknown backend {:?}, expected sysinfo or procfs",
+                other
+            )),
+        }
+    }
+}
+
+/// Opens the backend of `kind` and reads the parts in `plan` for the first
+/// time. Parts outside the plan are never read, so they cost nothing.
+///
+/// # Panics
+///
+/// If `kind` is not [available](BackendKind::is_available) in this build;
+/// the configuration is checked for that when it is loaded.
+pub fn open(kind: BackendKind, plan: Refresh) -> Box<dyn Backend> {
+    let mut backend: Box<dyn Backend> = match kind {
+        #[cfg(not(target_os = "wasi"))]
+        BackendKind::Sysinfo => Box::new(native::SysinfoBackend::new()),
+        BackendKind::Procfs => Box::new(proc::ProcBackend::new()),
+        #[allow(unreachable_patterns)]
+        other => panic!("the {} backend is not part of this build", other),
+    };
+    backend.refresh(plan);
+    backend
+}
+
+/// What `statvfs(3)` says about a mounted filesystem.
+#[derive(Debug, Clone, Copy)]
+pub struct FsStats {
+    pub total_bytes: u64,
+    /// Free blocks, including those reserved for root.
+    pub free_bytes: u64,
+    /// Free blocks available to unprivileged users.
+    pub available_bytes: u64,
+    pub inodes_total: u64,
+    pub inodes_free: u64,
+    pub read_only: bool,
+}
+
+#[cfg(unix)]
+pub fn statvfs(mount: &Path) -> Option<FsStats> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(mount.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stats` is a valid out-pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+
+    let fragment = stats.f_frsize as u64;
+    Some(FsStats {
+        total_bytes: stats.f_blocks as u64 * fragment,
+        free_bytes: stats.f_bfree as u64 * fragment,
+        available_bytes: stats.f_bavail as u64 * fragment,
+        inodes_total: stats.f_files as u64,
+        inodes_free: stats.f_ffree as u64,
+        read_only: stats.f_flag & libc::ST_RDONLY != 0,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn statvfs(_mount: &Path) -> Option<FsStats> {
+    None
+}
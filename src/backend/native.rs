@@ -0,0 +1,199 @@
+//! The backend built on sysinfo.
+
+use super::{Backend, Component, Cpu, Disk, LoadAverage, Memory, Network, Process, Refresh};
+use sysinfo::{
+    ComponentExt, DiskExt, NetworkExt, NetworksExt, Pid, ProcessExt, ProcessorExt, System,
+    SystemExt,
+};
+
+pub struct SysinfoBackend {
+    system: System,
+    own_pid: Option<Pid>,
+    /// Parts whose list of disks, interfaces or sensors has been read; until
+    /// then there is nothing for a plain refresh to update.
+    listed: Refresh,
+}
+
+impl Default for SysinfoBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SysinfoBackend {
+    /// Starts out empty: only what is later refreshed gets read.
+    pub fn new() -> Self {
+        SysinfoBackend {
+            system: System::new(),
+            own_pid: sysinfo::get_current_pid().ok(),
+            listed: Refresh::default(),
+        }
+    }
+}
+
+fn cpu(processor: &sysinfo::Processor) -> Cpu {
+    Cpu {
+        // Named after its /proc/stat line on Linux
+        id: processor
+            .name()
+            .strip_prefix("cpu")
+            .and_then(|n| n.parse().ok()),
+        usage: processor.cpu_usage(),
+        frequency: processor.frequency(),
+        vendor: processor.vendor_id().to_string(),
+        brand: processor.brand().to_string(),
+    }
+}
+
+fn process(process: &sysinfo::Process) -> Process {
+    let disk = process.disk_usage();
+    Process {
+        // The Pid type differs between platforms, its printed form does not
+        pid: process.pid().to_string().parse().unwrap_or(0),
+        name: process.name().to_string(),
+        cmd: process.cmd().to_vec(),
+        cpu_usage: process.cpu_usage(),
+        memory: process.memory() * 1024,
+        read_bytes: disk.total_read_bytes,
+        written_bytes: disk.total_written_bytes,
+        start_time: process.start_time(),
+    }
+}
+
+impl Backend for SysinfoBackend {
+    // Host name, load average and uptime are read when asked for
+    fn refresh(&mut self, what: Refresh) {
+        if what.memory {
+            self.system.refresh_memory();
+        }
+        if what.cpu {
+            self.system.refresh_cpu();
+        }
+        if what.disks {
+            if self.listed.disks {
+                self.system.refresh_disks();
+            } else {
+                self.system.refresh_disks_list();
+                self.listed.disks = true;
+            }
+        }
+        if what.networks {
+            if !self.listed.networks {
+                self.system.refresh_networks_list();
+                self.listed.networks = true;
+            }
+            self.system.refresh_networks();
+        }
+        if what.processes {
+            self.system.refresh_processes();
+        } else if what.agent {
+            if let Some(pid) = self.own_pid {
+                self.system.refresh_process(pid);
+            }
+        }
+        if what.components {
+            if self.listed.components {
+                self.system.refresh_components();
+            } else {
+                self.system.refresh_components_list();
+                self.listed.components = true;
+            }
+        }
+    }
+
+    fn host_name(&self) -> Option<String> {
+        self.system.host_name()
+    }
+
+    // sysinfo reports system memory in kB of 1000 bytes, having converted
+    // the KiB of /proc/meminfo, but process memory in KiB
+    fn memory(&self) -> Memory {
+        Memory {
+            total: self.system.total_memory() * 1000,
+            available: self.system.available_memory() * 1000,
+            swap_total: self.system.total_swap() * 1000,
+            swap_used: self.system.used_swap() * 1000,
+        }
+    }
+
+    fn global_cpu(&self) -> Cpu {
+        cpu(self.system.global_processor_info())
+    }
+
+    fn cpus(&self) -> Vec<Cpu> {
+        self.system.processors().iter().map(cpu).collect()
+    }
+
+    fn physical_core_count(&self) -> Option<usize> {
+        self.system.physical_core_count()
+    }
+
+    fn load_average(&self) -> LoadAverage {
+        let load = self.system.load_average();
+        LoadAverage {
+            one: load.one,
+            five: load.five,
+            fifteen: load.fifteen,
+        }
+    }
+
+    fn uptime(&self) -> u64 {
+        self.system.uptime()
+    }
+
+    fn boot_time(&self) -> u64 {
+        self.system.boot_time()
+    }
+
+    fn disks(&self) -> Vec<Disk> {
+        self.system
+            .disks()
+            .iter()
+            .map(|disk| Disk {
+                device: disk.name().to_string_lossy().into_owned(),
+                mount_point: disk.mount_point().to_path_buf(),
+                fs_type: String::from_utf8_lossy(disk.file_system()).into_owned(),
+                total: disk.total_space(),
+                available: disk.available_space(),
+            })
+            .collect()
+    }
+
+    fn networks(&self) -> Vec<Network> {
+        self.system
+            .networks()
+            .iter()
+            .map(|(name, data)| Network {
+                name: name.clone(),
+                rx_bytes: data.total_received(),
+                rx_packets: data.total_packets_received(),
+                rx_errors: data.total_errors_on_received(),
+                tx_bytes: data.total_transmitted(),
+                tx_packets: data.total_packets_transmitted(),
+                tx_errors: data.total_errors_on_transmitted(),
+            })
+            .collect()
+    }
+
+    fn processes(&self) -> Vec<Process> {
+        self.system.processes().values().map(process).collect()
+    }
+
+    fn components(&self) -> Vec<Component> {
+        self.system
+            .components()
+            .iter()
+            .map(|component| Component {
+                label: component.label().to_string(),
+                temperature: component.temperature(),
+                critical: component.critical(),
+            })
+            .collect()
+    }
+
+    fn agent(&self) -> Option<Process> {
+        self.own_pid
+            .and_then(|pid| self.system.process(pid))
+            .map(process)
+    }
+}
@@ -0,0 +1,682 @@
+//! The backend that reads `/proc` directly, for WASI and other targets
+//! sysinfo does not support.
+//!
+//! CPU usage, per core and per process, is the busy time between two
+//! refreshes, as sysinfo computes it. Filesystem space needs `statvfs(3)`,
+//! which WASI does not offer, so the disk list is empty there.
+
+use super::{
+    statvfs, Backend, Component, Cpu, Disk, LoadAverage, Memory, Network, Process, Refresh,
+};
+use crate::procfs;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Busy and total ticks of one `/proc/stat` CPU line.
+#[derive(Debug, Clone, Copy, Default)]
+struct Ticks {
+    busy: u64,
+    total: u64,
+}
+
+#[derive(Default)]
+pub struct ProcBackend {
+    clock_ticks: u64,
+    host_name: Option<String>,
+    memory: Memory,
+    global_cpu: Cpu,
+    cpus: Vec<Cpu>,
+    /// Read from `/proc/cpuinfo` on the first CPU refresh; it does not change
+    /// while the agent runs.
+    cpu_model: Option<CpuModel>,
+    /// Each core's cpufreq `scaling_cur_freq`, by N of `cpuN`. Empty when the
+    /// kernel has no cpufreq driver, and the clock comes from cpuinfo instead.
+    frequency_files: BTreeMap<usize, PathBuf>,
+    load: LoadAverage,
+    uptime: u64,
+    boot_time: u64,
+    disks: Vec<Disk>,
+    networks: Vec<Network>,
+    processes: Vec<Process>,
+    /// CPU ticks as of the previous refresh: the aggregate line first, then
+    /// each core.
+    previous_cpu: Vec<Ticks>,
+    /// CPU ticks used by each process as of the previous refresh, and when
+    /// that was.
+    previous_processes: HashMap<u32, u64>,
+    previous_at: Option<Instant>,
+    agent: Option<Process>,
+    /// CPU ticks the agent had used as of its previous refresh, and when.
+    previous_agent: Option<(u64, Instant)>,
+}
+
+impl ProcBackend {
+    pub fn new() -> Self {
+        ProcBackend {
+            clock_ticks: clock_ticks(),
+            ..Self::default()
+        }
+    }
+
+    fn refresh_cpu(&mut self, stat: &str) {
+        let lines: Vec<&str> = stat
+            .lines()
+            .filter(|line| line.starts_with("cpu"))
+            .collect();
+        let ticks: Vec<Ticks> = lines
+            .iter()
+            .map(|line| {
+                // user nice system idle iowait irq softirq steal
+                let fields: Vec<u64> = line
+                    .split_whitespace()
+                    .skip(1)
+                    .take(8)
+                    .map(|f| f.parse().unwrap_or(0))
+                    .collect();
+                let total: u64 = fields.iter().sum();
+                let idle =
+                    fields.get(3).copied().unwrap_or(0) + fields.get(4).copied().unwrap_or(0);
+                Ticks {
+                    busy: total.saturating_sub(idle),
+                    total,
+                }
+            })
+            .collect();
+
+        let usages: Vec<f32> = ticks
+            .iter()
+            .enumerate()
+            .map(|(i, now)| match self.previous_cpu.get(i) {
+                Some(before) if now.total > before.total => {
+                    let busy = now.busy.saturating_sub(before.busy) as f32;
+                    busy * 100.0 / (now.total - before.total) as f32
+                }
+                _ => 0.0,
+            })
+            .collect();
+        self.previous_cpu = ticks;
+
+        if self.cpu_model.is_none() {
+            let cpuinfo = procfs::read_proc("cpuinfo").unwrap_or_default();
+            self.cpu_model = Some(CpuModel::from_cpuinfo(&parse_cpuinfo(&cpuinfo)));
+            self.frequency_files = procfs::cpu_dirs()
+                .into_iter()
+                .map(|(id, dir)| (id, dir.join("cpufreq/scaling_cur_freq")))
+                .filter(|(_, file)| file.exists())
+                .collect();
+        }
+        let frequencies = self.frequencies();
+        let model = self.cpu_model.as_ref().expect("read above");
+
+        self.global_cpu = Cpu {
+            id: None,
+            usage: usages.first().copied().unwrap_or(0.0),
+            frequency: 0,
+            vendor: model.vendor.clone(),
+            brand: model.brand.clone(),
+        };
+        self.cpus = lines
+            .iter()
+            .zip(&usages)
+            .skip(1)
+            .map(|(line, usage)| {
+                let id = line
+                    .split_whitespace()
+                    .next()
+                    .and_then(|name| name.strip_prefix("cpu")?.parse().ok());
+                Cpu {
+                    id,
+                    usage: *usage,
+                    frequency: id.and_then(|id| frequencies.get(&id).copied()).unwrap_or(0),
+                    vendor: model.vendor.clone(),
+                    brand: model.brand.clone(),
+                }
+            })
+            .collect();
+    }
+
+    /// Each core's current clock in MHz, by N of `cpuN`.
+    fn frequencies(&self) -> HashMap<usize, u64> {
+        if self.frequency_files.is_empty() {
+            let cpuinfo = procfs::read_proc("cpuinfo").unwrap_or_default();
+            return parse_cpuinfo_mhz(&cpuinfo);
+        }
+        self.frequency_files
+            .iter()
+            .filter_map(|(id, file)| Some((*id, procfs::read_u64(file)? / 1000)))
+            .collect()
+    }
+
+    fn refresh_processes(&mut self, elapsed: Option<f64>) {
+        let entries = match std::fs::read_dir(procfs::proc_path("")) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        let mut processes = Vec::new();
+        let mut ticks_by_pid = HashMap::new();
+        for entry in entries.flatten() {
+            let dir = entry.file_name();
+            let dir = match dir.to_str() {
+                Some(dir) if dir.parse::<u32>().is_ok() => dir,
+                _ => continue,
+            };
+            // Processes can exit between listing and reading them
+            let (mut process, ticks) = match self.read_process(dir) {
+                Some(read) => read,
+                None => continue,
+            };
+            if let (Some(before), Some(elapsed)) =
+                (self.previous_processes.get(&process.pid), elapsed)
+            {
+                process.cpu_usage = self.cpu_usage(ticks.saturating_sub(*before), elapsed);
+            }
+            ticks_by_pid.insert(process.pid, ticks);
+            processes.push(process);
+        }
+
+        self.processes = processes;
+        self.previous_processes = ticks_by_pid;
+    }
+
+    /// Reads the agent through `/proc/self`, which works where the process
+    /// id is not available, as on WASI.
+    fn refresh_agent(&mut self) {
+        let now = Instant::now();
+        let (mut process, ticks) = match self.read_process("self") {
+            Some(read) => read,
+            None => {
+                self.agent = None;
+                return;
+            }
+        };
+        if let Some((before, at)) = self.previous_agent {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            process.cpu_usage = self.cpu_usage(ticks.saturating_sub(before), elapsed);
+        }
+        self.previous_agent = Some((ticks, now));
+        self.agent = Some(process);
+    }
+
+    /// Reads the process in `/proc/<dir>`, with no CPU usage yet, and the
+    /// CPU ticks it has used.
+    fn read_process(&self, dir: &str) -> Option<(Process, u64)> {
+        let stat = procfs::read_proc(&format!("{}/stat", dir)).ok()?;
+        let pid = stat.split_whitespace().next()?.parse().ok()?;
+        let (name, fields) = parse_pid_stat(&stat)?;
+
+        // Fields are numbered from 1 in proc(5); `fields` starts at 3 (state)
+        let field =
+            |n: usize| -> u64 { fields.get(n - 3).and_then(|f| f.parse().ok()).unwrap_or(0) };
+        let ticks = field(14) + field(15);
+
+        let cmd = procfs::read_proc(&format!("{}/cmdline", dir))
+            .map(|cmdline| {
+                cmdline
+                    .split('\0')
+                    .filter(|arg| !arg.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let status = procfs::read_proc(&format!("{}/status", dir)).unwrap_or_default();
+        let io = procfs::read_proc(&format!("{}/io", dir)).unwrap_or_default();
+
+        let process = Process {
+            pid,
+            name,
+            cmd,
+            cpu_usage: 0.0,
+            memory: parse_keyed(&status, "VmRSS") * 1024,
+            read_bytes: parse_keyed(&io, "read_bytes"),
+            written_bytes: parse_keyed(&io, "write_bytes"),
+            start_time: self.boot_time + field(22) / self.clock_ticks,
+        };
+        Some((process, ticks))
+    }
+
+    /// `ticks` of CPU time used over `elapsed` seconds, where 100% is one
+    /// core.
+    fn cpu_usage(&self, ticks: u64, elapsed: f64) -> f32 {
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        let seconds = ticks as f64 / self.clock_ticks as f64;
+        (seconds / elapsed * 100.0) as f32
+    }
+}
+
+impl Backend for ProcBackend {
+    fn refresh(&mut self, what: Refresh) {
+        if what.system {
+            self.host_name = procfs::read_proc("sys/kernel/hostname")
+                .ok()
+                .map(|name| name.trim().to_string());
+        }
+
+        if what.memory {
+            if let Ok(meminfo) = procfs::read_proc("meminfo") {
+                // In KiB, whatever the "kB" after each value says
+                let field = |name: &str| parse_keyed(&meminfo, name) * 1024;
+                let swap_total = field("SwapTotal");
+                self.memory = Memory {
+                    total: field("MemTotal"),
+                    available: field("MemAvailable"),
+                    swap_total,
+                    swap_used: swap_total.saturating_sub(field("SwapFree")),
+                };
+            }
+        }
+
+        // Process start times are counted from boot
+        let wants_boot_time = (what.processes || what.agent) && self.boot_time == 0;
+        if what.cpu || what.system || wants_boot_time {
+            if let Ok(stat) = procfs::read_proc("stat") {
+                self.boot_time = stat
+                    .lines()
+                    .find_map(|line| line.strip_prefix("btime ")?.trim().parse().ok())
+                    .unwrap_or(0);
+                if what.cpu {
+                    self.refresh_cpu(&stat);
+                }
+            }
+        }
+
+        if what.system {
+            if let Ok(loadavg) = procfs::read_proc("loadavg") {
+                let mut fields = loadavg.split_whitespace().map(|f| f.parse().unwrap_or(0.0));
+                self.load = LoadAverage {
+                    one: fields.next().unwrap_or(0.0),
+                    five: fields.next().unwrap_or(0.0),
+                    fifteen: fields.next().unwrap_or(0.0),
+                };
+            }
+
+            if let Ok(uptime) = procfs::read_proc("uptime") {
+                self.uptime = uptime
+                    .split_whitespace()
+                    .next()
+                    .and_then(|secs| secs.parse::<f64>().ok())
+                    .unwrap_or(0.0) as u64;
+            }
+        }
+
+        if what.disks {
+            self.disks = procfs::read_proc("mounts")
+                .map(|mounts| read_disks(&mounts))
+                .unwrap_or_default();
+        }
+        if what.networks {
+            self.networks = procfs::read_proc("net/dev")
+                .map(|dev| procfs::parse_net_dev(&dev))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|dev| Network {
+                    name: dev.name,
+                    rx_bytes: dev.rx_bytes,
+                    rx_packets: dev.rx_packets,
+                    rx_errors: dev.rx_errors,
+                    tx_bytes: dev.tx_bytes,
+                    tx_packets: dev.tx_packets,
+                    tx_errors: dev.tx_errors,
+                })
+                .collect();
+        }
+        if what.processes {
+            let now = Instant::now();
+            let elapsed = self
+                .previous_at
+                .map(|at| now.duration_since(at).as_secs_f64());
+            self.refresh_processes(elapsed);
+            self.previous_at = Some(now);
+        }
+        if what.agent {
+            self.refresh_agent();
+        }
+    }
+
+    fn host_name(&self) -> Option<String> {
+        self.host_name.clone()
+    }
+
+    fn memory(&self) -> Memory {
+        self.memory
+    }
+
+    fn global_cpu(&self) -> Cpu {
+        self.global_cpu.clone()
+    }
+
+    fn cpus(&self) -> Vec<Cpu> {
+        self.cpus.clone()
+    }
+
+    fn physical_core_count(&self) -> Option<usize> {
+        self.cpu_model.as_ref()?.physical_cores
+    }
+
+    fn load_average(&self) -> LoadAverage {
+        self.load
+    }
+
+    fn uptime(&self) -> u64 {
+        self.uptime
+    }
+
+    fn boot_time(&self) -> u64 {
+        self.boot_time
+    }
+
+    fn disks(&self) -> Vec<Disk> {
+        self.disks.clone()
+    }
+
+    fn networks(&self) -> Vec<Network> {
+        self.networks.clone()
+    }
+
+    fn processes(&self) -> Vec<Process> {
+        self.processes.clone()
+    }
+
+    /// Sensors are read from hwmon by their own collector.
+    fn components(&self) -> Vec<Component> {
+        Vec::new()
+    }
+
+    fn agent(&self) -> Option<Process> {
+        self.agent.clone()
+    }
+}
+
+/// What `/proc/cpuinfo` says about one logical CPU.
+struct CpuInfo {
+    vendor: String,
+    brand: String,
+    package: Option<String>,
+    core: Option<String>,
+}
+
+/// What `/proc/cpuinfo` says about the CPUs as a whole.
+#[derive(Debug, PartialEq)]
+struct CpuModel {
+    vendor: String,
+    brand: String,
+    physical_cores: Option<usize>,
+}
+
+impl CpuModel {
+    fn from_cpuinfo(info: &[CpuInfo]) -> Self {
+        let cores: HashSet<(&str, &str)> = info
+            .iter()
+            .filter_map(|info| Some((info.package.as_deref()?, info.core.as_deref()?)))
+            .collect();
+        CpuModel {
+            vendor: info.first().map(|i| i.vendor.clone()).unwrap_or_default(),
+            brand: info.first().map(|i| i.brand.clone()).unwrap_or_default(),
+            physical_cores: if cores.is_empty() {
+                None
+            } else {
+                Some(cores.len())
+            },
+        }
+    }
+}
+
+/// Parses the blank-line separated blocks of `/proc/cpuinfo`. x86 names the
+/// model; ARM only has the implementer code.
+fn parse_cpuinfo(text: &str) -> Vec<CpuInfo> {
+    text.split("\n\n")
+        .filter(|block| block.contains("processor"))
+        .map(|block| {
+            let fields: HashMap<&str, &str> = block
+                .lines()
+                .filter_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    Some((key.trim(), value.trim()))
+                })
+                .collect();
+            let field = |keys: &[&str]| {
+                keys.iter()
+                    .find_map(|k| fields.get(k))
+                    .map(|v| v.to_string())
+            };
+            CpuInfo {
+                vendor: field(&["vendor_id", "CPU implementer"]).unwrap_or_default(),
+                brand: field(&["model name", "Model", "Hardware"]).unwrap_or_default(),
+                package: field(&["physical id"]),
+                core: field(&["core id"]),
+            }
+        })
+        .collect()
+}
+
+/// Only the `cpu MHz` of each `processor` in `/proc/cpuinfo`, for kernels
+/// without cpufreq, where it is the only clock there is.
+fn parse_cpuinfo_mhz(text: &str) -> HashMap<usize, u64> {
+    let mut mhz = HashMap::new();
+    let mut id = None;
+    for line in text.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        match key {
+            "processor" => id = value.parse().ok(),
+            "cpu MHz" => {
+                if let (Some(id), Ok(value)) = (id, value.parse::<f64>()) {
+                    mhz.insert(id, value as u64);
+                }
+            }
+            _ => {}
+        }
+    }
+    mhz
+}
+
+/// The number after `key:` in files like `/proc/meminfo` and
+/// `/proc/<pid>/status`, or 0 if there is none.
+fn parse_keyed(text: &str, key: &str) -> u64 {
+    text.lines()
+        .find_map(|line| {
+            let value = line.strip_prefix(key)?.strip_prefix(':')?;
+            value.split_whitespace().next()?.parse().ok()
+        })
+        .unwrap_or(0)
+}
+
+/// Splits `/proc/<pid>/stat` into the command name and the fields after it.
+/// The name is in parentheses and may itself contain spaces and parentheses.
+fn parse_pid_stat(stat: &str) -> Option<(String, Vec<&str>)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    Some((name, stat[close + 1..].split_whitespace().collect()))
+}
+
+/// Mounts of real devices, leaving out pseudo filesystems as sysinfo does.
+fn read_disks(mounts: &str) -> Vec<Disk> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (device, mount, fs_type) = (fields.first()?, fields.get(1)?, fields.get(2)?);
+            if !device.starts_with('/') {
+                return None;
+            }
+            let mount_point = PathBuf::from(mount.replace("\\040", " "));
+            let fs = statvfs(&mount_point)?;
+            Some(Disk {
+                device: device.to_string(),
+                mount_point,
+                fs_type: fs_type.to_string(),
+                total: fs.total_bytes,
+                available: fs.available_bytes,
+            })
+        })
+        .collect()
+}
+
+/// Ticks per second of the CPU times in `/proc`.
+#[cfg(unix)]
+fn clock_ticks() -> u64 {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// Linux fixes `USER_HZ` at 100 on every architecture that matters.
+#[cfg(not(unix))]
+fn clock_ticks() -> u64 {
+    100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpuinfo_on_x86() {
+        let text = "\
+processor\t: 0
+vendor_id\t: GenuineIntel
+model name\t: Intel(R) Core(TM) i7-8550U CPU @ 1.80GHz
+cpu MHz\t\t: 1992.003
+physical id\t: 0
+core id\t\t: 0
+
+processor\t: 1
+vendor_id\t: GenuineIntel
+model name\t: Intel(R) Core(TM) i7-8550U CPU @ 1.80GHz
+cpu MHz\t\t: 800.000
+physical id\t: 0
+core id\t\t: 1
+";
+        let info = parse_cpuinfo(text);
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].vendor, "GenuineIntel");
+        assert_eq!(info[0].brand, "Intel(R) Core(TM) i7-8550U CPU @ 1.80GHz");
+        assert_eq!(info[1].package.as_deref(), Some("0"));
+        assert_eq!(info[1].core.as_deref(), Some("1"));
+
+        let model = CpuModel::from_cpuinfo(&info);
+        assert_eq!(model.vendor, "GenuineIntel");
+        assert_eq!(model.physical_cores, Some(2));
+        let mhz = parse_cpuinfo_mhz(text);
+        assert_eq!(mhz.get(&0), Some(&1992));
+        assert_eq!(mhz.get(&1), Some(&800));
+    }
+
+    #[test]
+    fn cpuinfo_on_arm() {
+        let text = "\
+processor\t: 0
+BogoMIPS\t: 108.00
+CPU implementer\t: 0x41
+CPU part\t: 0xd08
+
+processor\t: 1
+BogoMIPS\t: 108.00
+CPU implementer\t: 0x41
+CPU part\t: 0xd08
+
+Hardware\t: BCM2835
+Model\t\t: Raspberry Pi 4 Model B Rev 1.4
+";
+        let info = parse_cpuinfo(text);
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].vendor, "0x41");
+        assert!(info[0].package.is_none());
+
+        assert_eq!(CpuModel::from_cpuinfo(&info).physical_cores, None);
+        assert!(parse_cpuinfo_mhz(text).is_empty());
+    }
+
+    #[test]
+    fn pid_stat_with_awkward_names() {
+        let stat = "1234 (tmux: server) S 1 1234 1234 0 -1 4194560 2 0 0 0 7 3 0 0 20 0 1 0 500";
+        let (name, fields) = parse_pid_stat(stat).unwrap();
+        assert_eq!(name, "tmux: server");
+        assert_eq!(fields[0], "S");
+        // utime and stime are fields 14 and 15
+        assert_eq!((fields[14 - 3], fields[15 - 3]), ("7", "3"));
+
+        let (name, _) = parse_pid_stat("99 (a) (b)) R 1").unwrap();
+        assert_eq!(name, "a) (b)");
+        assert!(parse_pid_stat("99 no name").is_none());
+    }
+
+    #[test]
+    fn keyed_values() {
+        let meminfo = "\
+MemTotal:        6158152 kB
+MemFree:          712340 kB
+MemAvailable:    5448320 kB
+SwapTotal:             0 kB
+";
+        assert_eq!(parse_keyed(meminfo, "MemTotal"), 6158152);
+        assert_eq!(parse_keyed(meminfo, "MemAvailable"), 5448320);
+        // A prefix of another key does not match it
+        assert_eq!(parse_keyed(meminfo, "Mem"), 0);
+        assert_eq!(parse_keyed(meminfo, "Missing"), 0);
+        assert_eq!(parse_keyed("read_bytes: 4096\n", "read_bytes"), 4096);
+    }
+
+    #[test]
+    fn cpu_usage_between_refreshes() {
+        let mut backend = ProcBackend::new();
+        backend.refresh_cpu(
+            "cpu  100 0 100 800 0 0 0 0 0 0\n\
+             cpu0 50 0 50 400 0 0 0 0 0 0\n\
+             cpu1 50 0 50 400 0 0 0 0 0 0\n\
+             intr 12345\n",
+        );
+        // Nothing to compare with yet
+        assert_eq!(backend.global_cpu.usage, 0.0);
+        assert_eq!(backend.cpus.len(), 2);
+
+        // cpu0 is fully busy, cpu1 idle; iowait counts as idle
+        backend.refresh_cpu(
+            "cpu  200 0 100 850 50 0 0 0 0 0\n\
+             cpu0 150 0 50 400 0 0 0 0 0 0\n\
+             cpu1 50 0 50 450 50 0 0 0 0 0\n",
+        );
+        assert_eq!(backend.global_cpu.usage, 50.0);
+        assert_eq!(backend.cpus[0].usage, 100.0);
+        assert_eq!(backend.cpus[1].usage, 0.0);
+    }
+
+    #[test]
+    fn cpus_keep_their_numbers_with_cores_offline() {
+        let mut backend = ProcBackend::new();
+        backend.refresh_cpu(
+            "cpu  100 0 100 800 0 0 0 0 0 0\n\
+             cpu0 50 0 50 400 0 0 0 0 0 0\n\
+             cpu2 50 0 50 400 0 0 0 0 0 0\n",
+        );
+        assert_eq!(backend.global_cpu.id, None);
+        let ids: Vec<Option<usize>> = backend.cpus.iter().map(|cpu| cpu.id).collect();
+        assert_eq!(ids, vec![Some(0), Some(2)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn disks_leave_out_pseudo_filesystems() {
+        let mounts = "\
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+tmpfs /run tmpfs rw,nosuid,nodev 0 0
+/dev/root / ext4 rw,relatime 0 0
+/dev/sdb1 /no/such\\040mount ext4 rw 0 0
+";
+        let disks = read_disks(mounts);
+        // The mount that is not there cannot be sized
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0].device, "/dev/root");
+        assert_eq!(disks[0].mount_point, PathBuf::from("/"));
+        assert_eq!(disks[0].fs_type, "ext4");
+        assert!(disks[0].total > 0);
+    }
+}
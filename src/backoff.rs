@@ -0,0 +1,199 @@
+//! Exponential backoff with jitter for reconnect attempts.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Computes the delay before the next attempt, doubling it after every
+/// failure up to `max`.
+///
+/// Each delay is picked at random from the upper half of the current step, so
+/// a fleet of agents that lost the same server does not reconnect in
+/// lockstep once it comes back.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+    rng: XorShift,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max: max.max(initial),
+            attempt: 0,
+            rng: XorShift::from_entropy(),
+        }
+    }
+
+    /// Returns the delay to wait before the next attempt and advances to the
+    /// next step.
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self
+            .initial
+            .checked_mul(1u32 << self.attempt.min(31))
+            .map_or(self.max, |d| d.min(self.max));
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = step / 2;
+        half + half.mul_f64(self.rng.next_f64())
+    }
+
+    /// Number of consecutive failures since the last reset.
+    pub fn attempts(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Starts over from the initial delay, typically after a success.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// A tiny xorshift generator; jitter doesn't need anything stronger.
+#[derive(Debug, Clone)]
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    /// Seeds the generator from the clock and process id so agents started
+    /// at the same moment still diverge, and from a counter so generators
+    /// created in the same instant by one agent do too.
+    pub(crate) fn from_entropy() -> Self {
+        static CREATED: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let count = CREATED.fetch_add(1, Ordering::Relaxed);
+        let seed = nanos
+            ^ (process_salt() << 32)
+            ^ count.wrapping_mul(0xBF58_476D_1CE4_E5B9)
+            ^ 0x9E37_79B9_7F4A_7C15;
+        XorShift(seed.max(1))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// A uniformly distributed value in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Tells apart agents started at the same moment.
+#[cfg(not(target_os = "wasi"))]
+fn process_salt() -> u64 {
+    std::process::id() as u64
+}
+
+/// WASI has no process id (`std::process::id` panics there). The keys std
+/// draws for hash maps come from the runtime's `random_get` instead.
+#[cfg(target_os = "wasi")]
+fn process_salt() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(initial_ms: u64, max_ms: u64) -> Backoff {
+        Backoff {
+            rng: XorShift(0x2545_F491_4F6C_DD1D),
+            ..Backoff::new(
+                Duration::from_millis(initial_ms),
+                Duration::from_millis(max_ms),
+            )
+        }
+    }
+
+    /// Checks that `delay` was drawn from a step of `step_ms`, that is that it
+    /// lies in `[step/2, step)`.
+    fn assert_within(delay: Duration, step_ms: u64) {
+        let step = Duration::from_millis(step_ms);
+        assert!(
+            delay >= step / 2 && delay < step,
+            "{:?} is not within [{:?}, {:?})",
+            delay,
+            step / 2,
+            step
+        );
+    }
+
+    #[test]
+    fn doubles_after_every_failure() {
+        let mut backoff = backoff(100, 60_000);
+        for step in [100, 200, 400, 800, 1600, 3200] {
+            assert_within(backoff.next_delay(), step);
+        }
+        assert_eq!(backoff.attempts(), 6);
+    }
+
+    #[test]
+    fn stops_at_the_cap() {
+        let mut backoff = backoff(100, 1000);
+        for _ in 0..4 {
+            backoff.next_delay();
+        }
+        for _ in 0..100 {
+            assert_within(backoff.next_delay(), 1000);
+        }
+        // The step stays capped long after it would have overflowed
+        backoff.attempt = u32::MAX;
+        assert_within(backoff.next_delay(), 1000);
+    }
+
+    #[test]
+    fn jitter_spreads_over_the_upper_half() {
+        let mut backoff = backoff(1000, 1000);
+        let delays: Vec<Duration> = (0..1000).map(|_| backoff.next_delay()).collect();
+        for delay in &delays {
+            assert_within(*delay, 1000);
+        }
+        // Not all the same, and reaching both ends of the range
+        assert!(delays.iter().any(|d| *d < Duration::from_millis(550)));
+        assert!(delays.iter().any(|d| *d > Duration::from_millis(950)));
+    }
+
+    #[test]
+    fn reset_goes_back_to_the_initial_delay() {
+        let mut backoff = backoff(100, 60_000);
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        assert_within(backoff.next_delay(), 100);
+        assert_within(backoff.next_delay(), 200);
+    }
+
+    #[test]
+    fn the_cap_is_at_least_the_initial_delay() {
+        let mut backoff = backoff(500, 100);
+        assert_within(backoff.next_delay(), 500);
+        assert_within(backoff.next_delay(), 500);
+    }
+
+    #[test]
+    fn fixed_seeds_repeat_and_generators_diverge() {
+        let (mut a, mut b) = (backoff(100, 60_000), backoff(100, 60_000));
+        for _ in 0..10 {
+            assert_eq!(a.next_delay(), b.next_delay());
+        }
+
+        let (mut a, mut b) = (XorShift::from_entropy(), XorShift::from_entropy());
+        assert_ne!(a.next_u64(), b.next_u64());
+    }
+}
@@ -1,42 +1,122 @@
-use std::net::TcpStream;
-use std::io::Write;
-use sysinfo::{System, SystemExt, ProcessorExt};
-use std::time::Duration;
+use std::time::Instant;
 use std::thread;
+use std::process;
+use wasi_metrics::backend::{self, Refresh};
+use wasi_metrics::collectors::Registry;
+use wasi_metrics::config::{self, ClientConfig, Loaded};
+use wasi_metrics::metrics::now_millis;
+use wasi_metrics::procfs;
+use wasi_metrics::sinks;
+use wasi_metrics::Batch;
 
-fn send_data(data: String) {
-    match TcpStream::connect("127.0.0.1:8080") {
-        Ok(mut stream) => {
-            if let Err(e) = stream.write(data.as_bytes()) {
-                eprintln!("Failed to send data: {}", e);
-            }
+const USAGE: &str = "\
+Usage: metrics_client [OPTIONS]
+
+Options:
+  --config <PATH>               TOML config file
+  --host-id <ID>                agent identity sent to the server (default: host name)
+  --interval <SECS>             seconds between collections (default: 5)
+  --backend <NAME>              sysinfo or procfs (default: sysinfo, procfs on WASI)
+  --proc-root <PATH>            where the host's /proc is mounted (default: /proc)
+  --sys-root <PATH>             where the host's /sys is mounted (default: /sys)
+  --server <ADDR>               server address; repeat or comma-separate for failover
+  --timeout <SECS>              connect and reply timeout (default: 10)
+  --backoff-initial-ms <MS>     first reconnect delay, doubled per failure (default: 500)
+  --backoff-max-secs <SECS>     longest reconnect delay (default: 60)
+  --spool-dir <PATH>            directory for batches awaiting delivery
+  --spool-max-bytes <BYTES>     size cap of the spool
+  --spool-segment-bytes <BYTES> size of one spool segment file
+  --drop-policy <POLICY>        drop_oldest or drop_newest when the spool is full
+  --sinks <NAMES>               server, file, stdout and/or http (default: server,
+                                stdout on WASI)
+  --sink-file <PATH>            JSON lines file of the file sink (default: metrics.jsonl)
+  --sink-http <URL>             endpoint the http sink POSTs to
+  --collectors <NAMES>          collectors to run (default: all in the build)
+  --disable-collectors <NAMES>  collectors to leave out
+  --help                        print this message
+
+Every option can also be set with a WASI_METRICS_* environment variable,
+e.g. WASI_METRICS_HOST_ID or WASI_METRICS_CONFIG.
+";
+
+fn main() {
+    let config = match config::load::<ClientConfig>(std::env::args().skip(1)) {
+        Ok(Loaded::Config(config)) => config,
+        Ok(Loaded::Help) => {
+            print!("{}", USAGE);
+            return;
         }
         Err(e) => {
-            eprintln!("Could not connect to server: {}", e);
+            eprintln!("Error: {}", e);
+            eprint!("{}", USAGE);
+            process::exit(2);
         }
-    }
-}
+    };
 
-fn main() {
-    let mut system = System::new_all();
+    procfs::set_roots(config.agent.proc_root.clone(), config.agent.sys_root.clone());
+    let mut collectors = Registry::from_config(&config.collectors, config.interval())
+        .expect("collectors are checked when the config is loaded");
+
+    // Only what the enabled collectors read is ever refreshed; the system
+    // part also holds the host name
+    let plan = collectors.needs().union(Refresh {
+        system: true,
+        ..Refresh::default()
+    });
+    let mut backend = backend::open(config.agent.backend, plan);
+    let host_id = config
+        .agent
+        .host_id
+        .clone()
+        .or_else(|| backend.host_name())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // Sequence numbers restart with every run, so tag them with when it started
+    let session = now_millis();
+    let mut seq = 0;
 
-    // Set a periodic collection of metrics (e.g., every 5 seconds)
+    // Every batch goes to each configured sink, which queues and retries
+    // on its own
+    let mut sinks = match sinks::open(&config, &host_id) {
+        Ok(sinks) => sinks,
+        Err(e) => {
+            eprintln!("Could not open sinks: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Run each collector as often as configured, every 5 seconds unless
+    // configured otherwise
     loop {
-        // Refresh system data to make sure metrics are up to date
-        system.refresh_all();
-
-        // Collect the system metrics into a formatted string
-        let metrics = format!(
-            "Total memory: {} KB\nAvailable memory: {} KB\nCPU load: {}%\n",
-            system.total_memory(),
-            system.available_memory(),
-            system.processors()[0].cpu_usage()
-        );
-
-        // Attempt to send the data to the server
-        send_data(metrics);
-
-        // Wait for 5 seconds before collecting metrics again
-        thread::sleep(Duration::from_secs(5));
+        // Collect the due metrics into a batch of typed samples, refreshing
+        // only what those collectors read
+        let samples = collectors.collect_due(backend.as_mut());
+        let next_collection = collectors
+            .next_due()
+            .unwrap_or_else(|| Instant::now() + config.interval());
+
+        // Due collectors can all come up empty, e.g. process watches
+        // before any is configured
+        if !samples.is_empty() {
+            seq += 1;
+            let mut batch = Batch::new(&host_id, session, seq);
+            batch.samples = samples;
+            sinks.send(&batch);
+        }
+
+        // Deliver everything the sinks have queued, retrying as their
+        // backoff allows until the next collection is due
+        loop {
+            sinks.flush();
+
+            let wake = match sinks.next_due() {
+                Some(due) => due.min(next_collection),
+                None => next_collection,
+            };
+            thread::sleep(wake.saturating_duration_since(Instant::now()));
+            if wake >= next_collection {
+                break;
+            }
+        }
     }
 }
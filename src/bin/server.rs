@@ -1,36 +1,263 @@
-use std::net::TcpListener;
-use std::io::{Read, Write};
+use std::io::{self, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use wasi_metrics::config::{self, Loaded, ServerConfig};
+use wasi_metrics::dedup::Deduplicator;
+use wasi_metrics::events::EventDetector;
+use wasi_metrics::protocol::{self, Ack, Busy, Message, ProtocolError, Unsupported, Welcome};
+use wasi_metrics::Batch;
 
-fn main() -> std::io::Result<()> {
-    // Start a server listening on port 8080
-    let listener = TcpListener::bind("127.0.0.1:8080")?;
+const USAGE: &str = "\
+Usage: server [OPTIONS]
 
-    println!("Server listening on port 8080...");
+Options:
+  --config <PATH>   TOML config file
+  --listen <ADDR>   address to accept agents on; repeat or comma-separate for several
+  --max-connections <N>
+                    agent connections served at once (default: 256)
+  --read-timeout <SECS>
+                    close connections idle for this long (default: 120)
+  --help            print this message
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let mut buffer = [0; 1024];
-                // Read the data sent from the client
-                match stream.read(&mut buffer) {
-                    Ok(size) => {
-                        println!(
-                            "Received data: {}",
-                            String::from_utf8_lossy(&buffer[..size])
-                        );
-                        // Optionally, send a response back to the client
-                        let response = "Data received\n";
-                        stream.write(response.as_bytes()).unwrap();
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to read from connection: {}", e);
+Every option can also be set with a WASI_METRICS_* environment variable,
+e.g. WASI_METRICS_LISTEN or WASI_METRICS_CONFIG.
+";
+
+/// State shared by every connection thread.
+struct Server {
+    config: ServerConfig,
+    dedup: Mutex<Deduplicator>,
+    events: Mutex<EventDetector>,
+    active: AtomicUsize,
+}
+
+/// Holds one of the `max_connections` slots until dropped.
+struct ConnectionSlot {
+    server: Arc<Server>,
+}
+
+impl Server {
+    fn try_acquire_slot(server: &Arc<Server>) -> Option<ConnectionSlot> {
+        let max = server.config.max_connections;
+        server
+            .active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| ConnectionSlot {
+                server: Arc::clone(server),
+            })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.server.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Serves one agent until it disconnects, goes quiet for longer than the
+/// read timeout, or breaks the protocol. Agents are expected to keep the
+/// connection open and send every batch over it.
+fn handle_connection(server: &Server, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    stream.set_read_timeout(Some(server.config.read_timeout()))?;
+    stream.set_write_timeout(Some(server.config.read_timeout()))?;
+
+    // Answer in our default version until the agent has said hello
+    let mut version = protocol::PROTOCOL_VERSION;
+
+    loop {
+        // Read whole frames until the agent closes the connection
+        let message = match protocol::read_message(stream) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(()),
+            Err(ProtocolError::Io(e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                println!(
+                    "Closing connection idle for more than {}s",
+                    server.config.read_timeout_secs
+                );
+                return Ok(());
+            }
+            Err(ProtocolError::UnsupportedVersion(v)) => {
+                reject(stream, format!("protocol version {} is not supported", v))?;
+                return Err(ProtocolError::UnsupportedVersion(v));
+            }
+            Err(e) => return Err(e),
+        };
+
+        match message {
+            Message::Hello(hello) => match protocol::negotiate(&hello.versions) {
+                Some(v) => {
+                    version = v;
+                    let welcome = Message::Welcome(Welcome { version });
+                    protocol::write_message(stream, version, &welcome)?;
+                }
+                None => {
+                    reject(
+                        stream,
+                        format!("none of the versions {:?} are supported", hello.versions),
+                    )?;
+                    return Ok(());
+                }
+            },
+            Message::Batch(batch) => {
+                // Retransmitted batches are acknowledged again but only stored once
+                let is_new = server.dedup.lock().unwrap().accept(&batch);
+                if is_new {
+                    print_batch(&batch);
+                    for event in server.events.lock().unwrap().inspect(&batch) {
+                        println!("EVENT {}", event);
                     }
+                } else {
+                    println!(
+                        "Dropping duplicate batch {} from {}",
+                        batch.seq, batch.host_id
+                    );
                 }
+                let ack = Message::Ack(Ack {
+                    session: batch.session,
+                    seq: batch.seq,
+                });
+                protocol::write_message(stream, version, &ack)?;
             }
+            other => {
+                eprintln!("Ignoring unexpected message type {}", other.type_id());
+            }
+        }
+    }
+}
+
+fn reject(stream: &mut TcpStream, reason: String) -> Result<(), ProtocolError> {
+    let unsupported = Message::Unsupported(Unsupported {
+        supported: protocol::SUPPORTED_VERSIONS.to_vec(),
+        reason,
+    });
+    protocol::write_message(stream, protocol::PROTOCOL_VERSION, &unsupported)
+}
+
+/// Tells an agent the server is full, so it backs off or tries another
+/// server instead of waiting for a reply.
+fn refuse(stream: TcpStream, max_connections: usize) {
+    let busy = Message::Busy(Busy {
+        reason: format!("already serving {} connections", max_connections),
+    });
+    let frame = match protocol::encode_frame(protocol::PROTOCOL_VERSION, &busy) {
+        Ok(frame) => frame,
+        Err(_) => return,
+    };
+    // The frame fits the empty send buffer of a new connection, so one write
+    // that never waits hands it over without holding up the accept loop
+    let _ = stream.set_nonblocking(true);
+    if (&stream).write(&frame).ok() == Some(frame.len()) {
+        let _ = stream.shutdown(Shutdown::Write);
+    }
+}
+
+fn print_batch(batch: &Batch) {
+    // Hold the lock for the whole batch so concurrent agents don't interleave
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let _ = writeln!(
+        out,
+        "Received batch {} with {} samples from {}",
+        batch.seq,
+        batch.samples.len(),
+        batch.host_id
+    );
+    for sample in &batch.samples {
+        let _ = writeln!(out, "  {}", sample);
+    }
+}
+
+fn serve(listener: TcpListener, server: Arc<Server>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
             Err(e) => {
                 eprintln!("Failed to establish a connection: {}", e);
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown peer".to_string(), |a: SocketAddr| a.to_string());
+
+        // Agents over the limit are turned away before they cost a thread
+        let slot = match Server::try_acquire_slot(&server) {
+            Some(slot) => slot,
+            None => {
+                let max = server.config.max_connections;
+                eprintln!("Refusing {}: already serving {} connections", peer, max);
+                refuse(stream, max);
+                continue;
             }
+        };
+
+        // Each agent gets its own thread so a slow one can't hold up the rest.
+        // The slot is released when the thread ends, or right away if it
+        // cannot be started.
+        let spawned = thread::Builder::new()
+            .name(format!("conn-{}", peer))
+            .spawn(move || {
+                let mut stream = stream;
+                if let Err(e) = handle_connection(&slot.server, &mut stream) {
+                    eprintln!("Closing connection from {}: {}", peer, e);
+                }
+            });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start a connection thread: {}", e);
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let config = match config::load::<ServerConfig>(std::env::args().skip(1)) {
+        Ok(Loaded::Config(config)) => config,
+        Ok(Loaded::Help) => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprint!("{}", USAGE);
+            process::exit(2);
         }
+    };
+
+    // Bind every address up front so a bad one fails before we start serving
+    let mut listeners = Vec::new();
+    for addr in &config.listen {
+        listeners.push(TcpListener::bind(addr)?);
+        println!("Server listening on {}...", addr);
+    }
+
+    // Batches from all listeners share one view of what was already stored
+    let server = Arc::new(Server {
+        config,
+        dedup: Mutex::new(Deduplicator::new()),
+        events: Mutex::new(EventDetector::new()),
+        active: AtomicUsize::new(0),
+    });
+    let handles: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let server = Arc::clone(&server);
+            thread::spawn(move || serve(listener, server))
+        })
+        .collect();
+
+    for handle in handles {
+        let _ = handle.join();
     }
 
     Ok(())
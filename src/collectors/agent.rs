@@ -0,0 +1,40 @@
+//! The agent's own CPU and memory use, to keep an eye on its overhead.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+
+#[derive(Default)]
+pub struct AgentCollector;
+
+impl AgentCollector {
+    pub fn new() -> Self {
+        AgentCollector
+    }
+}
+
+impl Collector for AgentCollector {
+    fn name(&self) -> &'static str {
+        "agent"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            agent: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let process = match backend.agent() {
+            Some(process) => process,
+            None => return Vec::new(),
+        };
+
+        // CPU usage is per core, like process.cpu
+        vec![
+            Sample::gauge("agent.cpu", Unit::Percent, process.cpu_usage as f64),
+            Sample::gauge("agent.memory.rss", Unit::Bytes, process.memory as f64),
+        ]
+    }
+}
@@ -0,0 +1,763 @@
+//! CPU, memory and I/O of containers and systemd slices, read from cgroups.
+//!
+//! Both the unified (v2) hierarchy and the per-controller v1 hierarchies under
+//! `/sys/fs/cgroup` are supported. On a host the tree is walked for Docker,
+//! containerd, CRI-O and Podman containers and the top-level systemd slices.
+//! Inside a container the host's tree is not visible, so only the agent's own
+//! cgroup is reported, labelled `container="self"`; its limits are the ones
+//! that actually apply to the agent and its neighbours.
+
+use crate::backend::Backend;
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// How deep to look for containers; Kubernetes nests them four levels down.
+const MAX_DEPTH: usize = 8;
+
+/// v1 has no "unlimited" marker and reports the largest page-aligned counter.
+const V1_UNLIMITED: u64 = 1 << 62;
+
+/// Container scopes as named by the systemd cgroup driver, and the runtime
+/// each prefix belongs to.
+const SCOPE_PREFIXES: &[(&str, &str)] = &[
+    ("docker-", "docker"),
+    ("cri-containerd-", "containerd"),
+    ("crio-", "crio"),
+    ("libpod-", "podman"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CgroupConfig {
+    /// Also report the slices directly under the root, such as
+    /// `system.slice` and `user.slice`.
+    pub include_slices: bool,
+    /// Whether the agent runs in a container, and so reports only its own
+    /// cgroup. Detected from the usual marker files and the agent's cgroup
+    /// when unset.
+    pub in_container: Option<bool>,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        CgroupConfig {
+            include_slices: true,
+            in_container: None,
+        }
+    }
+}
+
+/// Where the controller files of one cgroup live.
+enum Dirs {
+    Unified(PathBuf),
+    Legacy {
+        cpu: PathBuf,
+        cpuacct: PathBuf,
+        memory: PathBuf,
+        blkio: PathBuf,
+    },
+}
+
+struct Cgroup {
+    /// Path relative to the hierarchy root.
+    path: String,
+    container: String,
+    runtime: &'static str,
+    dirs: Dirs,
+}
+
+/// What one cgroup reports, normalised across v1 and v2.
+#[derive(Debug, Default, PartialEq)]
+struct Stats {
+    cpu_seconds: Option<f64>,
+    periods: Option<u64>,
+    throttled_periods: Option<u64>,
+    throttled_seconds: Option<f64>,
+    /// CPU quota in cores.
+    cpu_limit: Option<f64>,
+    memory_usage: Option<u64>,
+    memory_limit: Option<u64>,
+    oom_kills: Option<u64>,
+    io: Option<IoStats>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct IoStats {
+    read_bytes: u64,
+    write_bytes: u64,
+    read_ops: u64,
+    write_ops: u64,
+}
+
+pub struct CgroupCollector {
+    config: CgroupConfig,
+    in_container: bool,
+    /// CPU seconds used by each cgroup as of the previous collection.
+    previous_cpu: HashMap<String, (f64, Instant)>,
+}
+
+impl CgroupCollector {
+    pub fn new(config: CgroupConfig) -> Self {
+        let in_container = config.in_container.unwrap_or_else(detect_container);
+        CgroupCollector {
+            config,
+            in_container,
+            previous_cpu: HashMap::new(),
+        }
+    }
+
+    fn describe(&self, cgroup: &Cgroup, stats: &Stats, now: Instant) -> Vec<Sample> {
+        let mut samples = Vec::new();
+
+        if let Some(seconds) = stats.cpu_seconds {
+            samples.push(Sample::counter("cgroup.cpu.time", Unit::Seconds, seconds));
+            if let Some((before, at)) = self.previous_cpu.get(&cgroup.path) {
+                let elapsed = now.duration_since(*at).as_secs_f64();
+                if elapsed > 0.0 {
+                    // Like process.cpu, 100% is one core
+                    let usage = (seconds - before).max(0.0) / elapsed * 100.0;
+                    samples.push(Sample::gauge("cgroup.cpu.usage", Unit::Percent, usage));
+                }
+            }
+        }
+        if let Some(periods) = stats.periods {
+            samples.push(Sample::counter("cgroup.cpu.periods", Unit::None, periods as f64));
+        }
+        if let Some(throttled) = stats.throttled_periods {
+            samples.push(Sample::counter(
+                "cgroup.cpu.throttled_periods",
+                Unit::None,
+                throttled as f64,
+            ));
+        }
+        if let Some(seconds) = stats.throttled_seconds {
+            samples.push(Sample::counter(
+                "cgroup.cpu.throttled_time",
+                Unit::Seconds,
+                seconds,
+            ));
+        }
+        if let Some(cores) = stats.cpu_limit {
+            samples.push(Sample::gauge("cgroup.cpu.limit", Unit::None, cores));
+        }
+
+        if let Some(usage) = stats.memory_usage {
+            samples.push(Sample::gauge("cgroup.memory.usage", Unit::Bytes, usage as f64));
+        }
+        if let Some(limit) = stats.memory_limit {
+            samples.push(Sample::gauge("cgroup.memory.limit", Unit::Bytes, limit as f64));
+        }
+        if let Some(kills) = stats.oom_kills {
+            samples.push(Sample::counter(
+                "cgroup.memory.oom_kills",
+                Unit::None,
+                kills as f64,
+            ));
+        }
+
+        if let Some(io) = &stats.io {
+            samples.push(Sample::counter(
+                "cgroup.io.read_bytes",
+                Unit::Bytes,
+                io.read_bytes as f64,
+            ));
+            samples.push(Sample::counter(
+                "cgroup.io.write_bytes",
+                Unit::Bytes,
+                io.write_bytes as f64,
+            ));
+            samples.push(Sample::counter("cgroup.io.read_ops", Unit::None, io.read_ops as f64));
+            samples.push(Sample::counter(
+                "cgroup.io.write_ops",
+                Unit::None,
+                io.write_ops as f64,
+            ));
+        }
+
+        samples
+            .into_iter()
+            .map(|sample| {
+                sample
+                    .with_label("cgroup", cgroup.path.clone())
+                    .with_label("container", cgroup.container.clone())
+                    .with_label("runtime", cgroup.runtime)
+            })
+            .collect()
+    }
+}
+
+impl Collector for CgroupCollector {
+    fn name(&self) -> &'static str {
+        "cgroup"
+    }
+
+    fn collect(&mut self, _backend: &dyn Backend) -> Vec<Sample> {
+        let unified = cgroup_root().join("cgroup.controllers").exists();
+        let cgroups = if self.in_container {
+            own_cgroup(unified).into_iter().collect()
+        } else {
+            discover(unified, self.config.include_slices)
+        };
+
+        let now = Instant::now();
+        let mut cpu = HashMap::new();
+        let mut samples = Vec::new();
+        for cgroup in cgroups {
+            let stats = match &cgroup.dirs {
+                Dirs::Unified(dir) => unified_stats(dir),
+                Dirs::Legacy {
+                    cpu,
+                    cpuacct,
+                    memory,
+                    blkio,
+                } => legacy_stats(cpu, cpuacct, memory, blkio),
+            };
+            samples.extend(self.describe(&cgroup, &stats, now));
+            if let Some(seconds) = stats.cpu_seconds {
+                cpu.insert(cgroup.path, (seconds, now));
+            }
+        }
+        // Containers that went away are forgotten
+        self.previous_cpu = cpu;
+
+        samples
+    }
+}
+
+fn cgroup_root() -> PathBuf {
+    procfs::sys_path("fs/cgroup")
+}
+
+/// Where a v1 controller's hierarchy is mounted. `cpu` and `cpuacct` are
+/// usually links to a shared `cpu,cpuacct` mount.
+fn controller_root(controller: &str) -> PathBuf {
+    cgroup_root().join(controller)
+}
+
+/// Guesses whether the agent runs in a container from the files Docker and
+/// Podman leave behind, and from the cgroups of init and of the agent.
+fn detect_container() -> bool {
+    if Path::new("/.dockerenv").exists() || Path::new("/run/.containerenv").exists() {
+        return true;
+    }
+    let unified = cgroup_root().join("cgroup.controllers").exists();
+    in_container(
+        &procfs::read_proc("1/cgroup").unwrap_or_default(),
+        &procfs::read_proc("self/cgroup").unwrap_or_default(),
+        unified,
+    )
+}
+
+/// Decides from the `/proc/<pid>/cgroup` of init and of the agent. Without a
+/// cgroup namespace, init's cgroup names the runtime. With one, the default
+/// for containerd, Kubernetes and recent Docker on v2, the agent's cgroup is
+/// shown as the root, where on a host only processes outside any service or
+/// session are.
+fn in_container(init_cgroup: &str, own_cgroup: &str, unified: bool) -> bool {
+    let marked = ["docker", "kubepods", "containerd", "libpod", "lxc"]
+        .iter()
+        .any(|marker| init_cgroup.contains(marker));
+    marked || (unified && own_cgroup.trim() == "0::/")
+}
+
+/// The agent's own cgroup, as listed in `/proc/self/cgroup`.
+fn own_cgroup(unified: bool) -> Option<Cgroup> {
+    let text = procfs::read_proc("self/cgroup").ok()?;
+    // Lines are "id:controllers:path"; v2 has a single "0::path" line
+    let paths: HashMap<&str, &str> = text
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let _id = fields.next()?;
+            Some((fields.next()?, fields.next()?))
+        })
+        .flat_map(|(controllers, path)| controllers.split(',').map(move |c| (c, path)))
+        .collect();
+
+    let (path, dirs) = if unified {
+        let path = paths.get("")?;
+        (path.to_string(), Dirs::Unified(resolve(&cgroup_root(), path)))
+    } else {
+        let dir = |controller: &str| {
+            let path = paths.get(controller).copied().unwrap_or("/");
+            resolve(&controller_root(controller), path)
+        };
+        let path = paths.get("memory").copied().unwrap_or("/");
+        (
+            path.to_string(),
+            Dirs::Legacy {
+                cpu: dir("cpu"),
+                cpuacct: dir("cpuacct"),
+                memory: dir("memory"),
+                blkio: dir("blkio"),
+            },
+        )
+    };
+
+    Some(Cgroup {
+        path,
+        container: "self".to_string(),
+        runtime: "self",
+        dirs,
+    })
+}
+
+/// The directory of `path` under `mount`. With a cgroup namespace, or when
+/// the runtime bind-mounts the container's own cgroup as the root, the path
+/// in `/proc/self/cgroup` does not exist under the mount and the mount itself
+/// is the cgroup.
+fn resolve(mount: &Path, path: &str) -> PathBuf {
+    let dir = mount.join(path.trim_start_matches('/'));
+    if dir.is_dir() {
+        dir
+    } else {
+        mount.to_path_buf()
+    }
+}
+
+/// Walks the hierarchy for containers and, if asked, top-level slices. v1
+/// runtimes create the same path in every controller, so the memory
+/// hierarchy stands in for all of them.
+fn discover(unified: bool, include_slices: bool) -> Vec<Cgroup> {
+    let root = if unified {
+        cgroup_root()
+    } else {
+        controller_root("memory")
+    };
+
+    let mut found = Vec::new();
+    walk(&root, Path::new(""), 0, include_slices, &mut found);
+
+    found
+        .into_iter()
+        .map(|(rel, container, runtime)| {
+            let dirs = if unified {
+                Dirs::Unified(root.join(&rel))
+            } else {
+                Dirs::Legacy {
+                    cpu: controller_root("cpu").join(&rel),
+                    cpuacct: controller_root("cpuacct").join(&rel),
+                    memory: controller_root("memory").join(&rel),
+                    blkio: controller_root("blkio").join(&rel),
+                }
+            };
+            Cgroup {
+                path: format!("/{}", rel.display()),
+                container,
+                runtime,
+                dirs,
+            }
+        })
+        .collect()
+}
+
+fn walk(
+    root: &Path,
+    rel: &Path,
+    depth: usize,
+    include_slices: bool,
+    found: &mut Vec<(PathBuf, String, &'static str)>,
+) {
+    let entries = match fs::read_dir(root.join(rel)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        if !entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let child = rel.join(&name);
+
+        // A container's own sub-cgroups are its business, so stop there
+        if let Some((runtime, id)) = classify(&name, rel) {
+            found.push((child, id, runtime));
+            continue;
+        }
+        if depth == 0 && include_slices && name.ends_with(".slice") {
+            found.push((child.clone(), name.clone(), "systemd"));
+        }
+        if depth < MAX_DEPTH {
+            walk(root, &child, depth + 1, include_slices, found);
+        }
+    }
+}
+
+/// Recognises a container cgroup by its directory name, returning the
+/// runtime and the short container id.
+fn classify(name: &str, parent: &Path) -> Option<(&'static str, String)> {
+    if let Some(stem) = name.strip_suffix(".scope") {
+        for (prefix, runtime) in SCOPE_PREFIXES {
+            if let Some(id) = stem.strip_prefix(prefix) {
+                if is_container_id(id) {
+                    return Some((runtime, id[..12].to_string()));
+                }
+            }
+        }
+        return None;
+    }
+
+    // The cgroupfs driver names the directory after the bare id. Only Docker
+    // puts it under a directory of its own; under `kubepods` and elsewhere
+    // any CRI runtime could have made it
+    if is_container_id(name) {
+        let runtime = match parent.file_name().and_then(|n| n.to_str()) {
+            Some("docker") => "docker",
+            _ => "unknown",
+        };
+        return Some((runtime, name[..12].to_string()));
+    }
+    None
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn unified_stats(dir: &Path) -> Stats {
+    let cpu = read_keyed(&dir.join("cpu.stat"));
+    let events = read_keyed(&dir.join("memory.events"));
+    let micros = |key: &str| cpu.get(key).map(|us| *us as f64 / 1_000_000.0);
+
+    Stats {
+        cpu_seconds: micros("usage_usec"),
+        periods: cpu.get("nr_periods").copied(),
+        throttled_periods: cpu.get("nr_throttled").copied(),
+        throttled_seconds: micros("throttled_usec"),
+        // "max 100000" when unlimited, "<quota> <period>" otherwise
+        cpu_limit: fs::read_to_string(dir.join("cpu.max")).ok().and_then(|text| {
+            let mut fields = text.split_whitespace();
+            let quota: f64 = fields.next()?.parse().ok()?;
+            let period: f64 = fields.next()?.parse().ok()?;
+            Some(quota / period)
+        }),
+        memory_usage: procfs::read_u64(&dir.join("memory.current")),
+        // Unlimited is "max", which does not parse
+        memory_limit: procfs::read_u64(&dir.join("memory.max")),
+        oom_kills: events.get("oom_kill").copied(),
+        io: fs::read_to_string(dir.join("io.stat")).ok().map(|text| {
+            let mut io = IoStats::default();
+            // One line per device: "8:0 rbytes=1 wbytes=2 rios=3 wios=4 ..."
+            for field in text.split_whitespace() {
+                let (key, value) = match field.split_once('=') {
+                    Some((key, value)) => (key, value.parse().unwrap_or(0)),
+                    None => continue,
+                };
+                match key {
+                    "rbytes" => io.read_bytes += value,
+                    "wbytes" => io.write_bytes += value,
+                    "rios" => io.read_ops += value,
+                    "wios" => io.write_ops += value,
+                    _ => {}
+                }
+            }
+            io
+        }),
+    }
+}
+
+fn legacy_stats(cpu: &Path, cpuacct: &Path, memory: &Path, blkio: &Path) -> Stats {
+    let cpu_stat = read_keyed(&cpu.join("cpu.stat"));
+    let oom = read_keyed(&memory.join("memory.oom_control"));
+
+    let quota = fs::read_to_string(cpu.join("cpu.cfs_quota_us"))
+        .ok()
+        .and_then(|text| text.trim().parse::<i64>().ok());
+    let period = procfs::read_u64(&cpu.join("cpu.cfs_period_us"));
+
+    let io = match (
+        fs::read_to_string(blkio.join("blkio.throttle.io_service_bytes")),
+        fs::read_to_string(blkio.join("blkio.throttle.io_serviced")),
+    ) {
+        (Ok(bytes), Ok(ops)) => {
+            let (read_bytes, write_bytes) = sum_blkio(&bytes);
+            let (read_ops, write_ops) = sum_blkio(&ops);
+            Some(IoStats {
+                read_bytes,
+                write_bytes,
+                read_ops,
+                write_ops,
+            })
+        }
+        _ => None,
+    };
+
+    Stats {
+        // v1 counts nanoseconds
+        cpu_seconds: procfs::read_u64(&cpuacct.join("cpuacct.usage"))
+            .map(|ns| ns as f64 / 1_000_000_000.0),
+        periods: cpu_stat.get("nr_periods").copied(),
+        throttled_periods: cpu_stat.get("nr_throttled").copied(),
+        throttled_seconds: cpu_stat
+            .get("throttled_time")
+            .map(|ns| *ns as f64 / 1_000_000_000.0),
+        // A quota of -1 means unlimited
+        cpu_limit: match (quota, period) {
+            (Some(quota), Some(period)) if quota > 0 && period > 0 => {
+                Some(quota as f64 / period as f64)
+            }
+            _ => None,
+        },
+        memory_usage: procfs::read_u64(&memory.join("memory.usage_in_bytes")),
+        memory_limit: procfs::read_u64(&memory.join("memory.limit_in_bytes"))
+            .filter(|limit| *limit < V1_UNLIMITED),
+        // Only kernels since 4.13 count OOM kills per cgroup
+        oom_kills: oom.get("oom_kill").copied(),
+        io,
+    }
+}
+
+/// Parses a flat `key value` file such as `cpu.stat`.
+fn read_keyed(path: &Path) -> HashMap<String, u64> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return HashMap::new(),
+    };
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Sums the `Read` and `Write` lines of a v1 blkio file across devices.
+/// Lines look like "8:0 Read 4096", followed by a "Total" line.
+fn sum_blkio(text: &str) -> (u64, u64) {
+    let (mut read, mut write) = (0, 0);
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let (_device, op, value) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(device), Some(op), Some(value)) => (device, op, value),
+            _ => continue,
+        };
+        let value: u64 = value.parse().unwrap_or(0);
+        match op {
+            "Read" => read += value,
+            "Write" => write += value,
+            _ => {}
+        }
+    }
+    (read, write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4f1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+
+    #[test]
+    fn classifies_systemd_scopes() {
+        let parent = Path::new("system.slice");
+        for (prefix, runtime) in SCOPE_PREFIXES {
+            let name = format!("{}{}.scope", prefix, ID);
+            assert_eq!(
+                classify(&name, parent),
+                Some((*runtime, ID[..12].to_string()))
+            );
+        }
+        assert_eq!(classify("docker-abc.scope", parent), None);
+        assert_eq!(classify("session-3.scope", parent), None);
+    }
+
+    #[test]
+    fn classifies_bare_ids_by_their_parent() {
+        assert_eq!(
+            classify(ID, Path::new("docker")),
+            Some(("docker", ID[..12].to_string()))
+        );
+        assert_eq!(
+            classify(ID, Path::new("kubepods/burstable/pod1234")),
+            Some(("unknown", ID[..12].to_string()))
+        );
+        assert_eq!(classify("sshd.service", Path::new("system.slice")), None);
+    }
+
+    #[test]
+    fn detects_containers_by_init_cgroup() {
+        let docker = "12:memory:/docker/4f1b2c3d4e5f\n0::/docker/4f1b2c3d4e5f\n";
+        assert!(in_container(docker, docker, false));
+        let kubepods = "0::/kubepods/burstable/pod1234/4f1b2c3d4e5f\n";
+        assert!(in_container(kubepods, kubepods, true));
+    }
+
+    #[test]
+    fn detects_containers_in_a_cgroup_namespace() {
+        assert!(in_container("0::/\n", "0::/\n", true));
+        // v1 has no cgroup namespace to speak of by default
+        assert!(!in_container("12:memory:/\n", "12:memory:/\n", false));
+    }
+
+    #[test]
+    fn a_host_is_not_a_container() {
+        let init = "0::/init.scope\n";
+        let agent = "0::/system.slice/wasi-metrics.service\n";
+        assert!(!in_container(init, agent, true));
+        let init = "12:memory:/init.scope\n1:name=systemd:/init.scope\n";
+        let agent = "12:memory:/system.slice/wasi-metrics.service\n";
+        assert!(!in_container(init, agent, false));
+    }
+
+    /// A directory holding `files`, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "wasi-metrics-cgroup-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in files {
+                fs::write(dir.join(file), contents).unwrap();
+            }
+            Fixture(dir)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_unified_stats() {
+        let dir = Fixture::new(
+            "v2",
+            &[
+                (
+                    "cpu.stat",
+                    "usage_usec 2500000\nuser_usec 2000000\nsystem_usec 500000\n\
+                     nr_periods 40\nnr_throttled 4\nthrottled_usec 250000\n",
+                ),
+                ("cpu.max", "150000 100000\n"),
+                ("memory.current", "52428800\n"),
+                ("memory.max", "max\n"),
+                ("memory.events", "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n"),
+                (
+                    "io.stat",
+                    "8:0 rbytes=4096 wbytes=8192 rios=1 wios=2 dbytes=0 dios=0\n\
+                     8:16 rbytes=1024 wbytes=0 rios=3 wios=0 dbytes=0 dios=0\n",
+                ),
+            ],
+        );
+        assert_eq!(
+            unified_stats(&dir.0),
+            Stats {
+                cpu_seconds: Some(2.5),
+                periods: Some(40),
+                throttled_periods: Some(4),
+                throttled_seconds: Some(0.25),
+                cpu_limit: Some(1.5),
+                memory_usage: Some(52_428_800),
+                memory_limit: None,
+                oom_kills: Some(1),
+                io: Some(IoStats {
+                    read_bytes: 5120,
+                    write_bytes: 8192,
+                    read_ops: 4,
+                    write_ops: 2,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_unified_limits_and_missing_controllers() {
+        let dir = Fixture::new(
+            "v2-limits",
+            &[("cpu.max", "max 100000\n"), ("memory.max", "1073741824\n")],
+        );
+        let stats = unified_stats(&dir.0);
+        assert_eq!(stats.cpu_limit, None);
+        assert_eq!(stats.memory_limit, Some(1 << 30));
+        assert_eq!(stats.cpu_seconds, None);
+        assert_eq!(stats.io, None);
+    }
+
+    #[test]
+    fn reads_legacy_stats() {
+        let dir = Fixture::new(
+            "v1",
+            &[
+                ("cpuacct.usage", "3000000000\n"),
+                (
+                    "cpu.stat",
+                    "nr_periods 10\nnr_throttled 2\nthrottled_time 500000000\n",
+                ),
+                ("cpu.cfs_quota_us", "50000\n"),
+                ("cpu.cfs_period_us", "100000\n"),
+                ("memory.usage_in_bytes", "1048576\n"),
+                ("memory.limit_in_bytes", "9223372036854771712\n"),
+                (
+                    "memory.oom_control",
+                    "oom_kill_disable 0\nunder_oom 0\noom_kill 2\n",
+                ),
+                (
+                    "blkio.throttle.io_service_bytes",
+                    "8:0 Read 4096\n8:0 Write 8192\n8:0 Total 12288\nTotal 12288\n",
+                ),
+                (
+                    "blkio.throttle.io_serviced",
+                    "8:0 Read 1\n8:0 Write 2\n8:0 Total 3\nTotal 3\n",
+                ),
+            ],
+        );
+        let d = &dir.0;
+        assert_eq!(
+            legacy_stats(d, d, d, d),
+            Stats {
+                cpu_seconds: Some(3.0),
+                periods: Some(10),
+                throttled_periods: Some(2),
+                throttled_seconds: Some(0.5),
+                cpu_limit: Some(0.5),
+                memory_usage: Some(1_048_576),
+                memory_limit: None,
+                oom_kills: Some(2),
+                io: Some(IoStats {
+                    read_bytes: 4096,
+                    write_bytes: 8192,
+                    read_ops: 1,
+                    write_ops: 2,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_an_unlimited_legacy_quota() {
+        let dir = Fixture::new(
+            "v1-limits",
+            &[
+                ("cpu.cfs_quota_us", "-1\n"),
+                ("cpu.cfs_period_us", "100000\n"),
+                ("memory.limit_in_bytes", "536870912\n"),
+            ],
+        );
+        let d = &dir.0;
+        let stats = legacy_stats(d, d, d, d);
+        assert_eq!(stats.cpu_limit, None);
+        assert_eq!(stats.memory_limit, Some(512 << 20));
+        assert_eq!(stats.io, None);
+    }
+
+    #[test]
+    fn sums_blkio_across_devices() {
+        let text = "8:0 Read 100\n8:0 Write 200\n8:0 Sync 300\n8:0 Total 300\n\
+                    8:16 Read 10\n8:16 Write 20\n8:16 Total 30\nTotal 330\n";
+        assert_eq!(sum_blkio(text), (110, 220));
+        assert_eq!(sum_blkio(""), (0, 0));
+    }
+}
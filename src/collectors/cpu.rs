@@ -0,0 +1,134 @@
+//! Per-core and aggregate CPU usage.
+//!
+//! Overall usage comes from the backend (`cpu.usage`). The split into user,
+//! system, iowait, steal and the other modes comes from `/proc/stat`
+//! (`cpu.mode`), computed from the tick counters between two collections.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Column order of the per-CPU lines in `/proc/stat`.
+const MODES: &[&str] = &[
+    "user", "nice", "system", "idle", "iowait", "irq", "softirq", "steal",
+];
+
+/// Label value used for the aggregate of all cores.
+pub const TOTAL: &str = "total";
+
+#[derive(Default)]
+pub struct CpuCollector {
+    /// Tick counters from the previous `/proc/stat` read, keyed by CPU label.
+    previous: HashMap<String, Vec<u64>>,
+}
+
+impl CpuCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turns the tick counters of every CPU line into the share of time
+    /// spent in each mode since the previous call.
+    fn mode_breakdown(&mut self, stat: &str) -> Vec<Sample> {
+        let mut samples = Vec::new();
+
+        for (cpu, ticks) in parse_stat(stat) {
+            if let Some(previous) = self.previous.get(&cpu) {
+                let deltas: Vec<u64> = ticks
+                    .iter()
+                    .zip(previous)
+                    .map(|(now, before)| now.saturating_sub(*before))
+                    .collect();
+                let elapsed: u64 = deltas.iter().sum();
+                if elapsed > 0 {
+                    for (mode, delta) in MODES.iter().zip(&deltas) {
+                        samples.push(
+                            Sample::gauge(
+                                "cpu.mode",
+                                Unit::Percent,
+                                *delta as f64 * 100.0 / elapsed as f64,
+                            )
+                            .with_label("cpu", cpu.clone())
+                            .with_label("mode", *mode),
+                        );
+                    }
+                }
+            }
+            self.previous.insert(cpu, ticks);
+        }
+
+        samples
+    }
+}
+
+impl Collector for CpuCollector {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    // Usage is spiky, and a longer interval averages the spikes away
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(1))
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            cpu: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let mut samples = Vec::new();
+
+        samples.push(
+            Sample::gauge(
+                "cpu.usage",
+                Unit::Percent,
+                backend.global_cpu().usage as f64,
+            )
+            .with_label("cpu", TOTAL),
+        );
+        // Labelled like the /proc/stat lines of the mode breakdown
+        for (i, cpu) in backend.cpus().iter().enumerate() {
+            samples.push(
+                Sample::gauge("cpu.usage", Unit::Percent, cpu.usage as f64)
+                    .with_label("cpu", cpu.id.unwrap_or(i).to_string()),
+            );
+        }
+
+        if let Ok(stat) = procfs::read_proc("stat") {
+            samples.extend(self.mode_breakdown(&stat));
+        }
+
+        samples
+    }
+}
+
+/// Parses the `cpu` lines of `/proc/stat` into a label (`total` for the
+/// aggregate line, the core number otherwise) and the tick counter of every
+/// mode in [`MODES`].
+fn parse_stat(stat: &str) -> Vec<(String, Vec<u64>)> {
+    stat.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.strip_prefix("cpu")?;
+            let cpu = if name.is_empty() {
+                TOTAL.to_string()
+            } else {
+                name.to_string()
+            };
+
+            // Older kernels have fewer columns; missing modes count as zero
+            let mut ticks: Vec<u64> = fields
+                .take(MODES.len())
+                .map(|f| f.parse().unwrap_or(0))
+                .collect();
+            ticks.resize(MODES.len(), 0);
+            Some((cpu, ticks))
+        })
+        .collect()
+}
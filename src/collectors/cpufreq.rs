@@ -0,0 +1,106 @@
+//! Per-core clock frequency and thermal throttling.
+//!
+//! Frequencies come from cpufreq in `/sys/devices/system/cpu`, falling back
+//! to the backend's current frequency where cpufreq is missing (some VMs and
+//! older ARM kernels). Throttle counters are only kept by x86 kernels. The
+//! CPU topology is reported by the inventory collector.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+#[derive(Default)]
+pub struct CpuFreqCollector;
+
+impl CpuFreqCollector {
+    pub fn new() -> Self {
+        CpuFreqCollector
+    }
+}
+
+impl Collector for CpuFreqCollector {
+    fn name(&self) -> &'static str {
+        "cpufreq"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            cpu: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let cpus = procfs::cpu_dirs();
+        let mut samples = Vec::new();
+
+        for (i, cpu) in backend.cpus().iter().enumerate() {
+            // With cores offline, the backend's list has gaps its indexes
+            // do not show
+            let n = cpu.id.unwrap_or(i);
+            let cpufreq = cpus.get(&n).map(|dir| dir.join("cpufreq"));
+            // cpufreq reports kHz, the backend MHz
+            let khz = |name: &str| {
+                let dir = cpufreq.as_ref()?;
+                procfs::read_u64(&dir.join(name)).map(|khz| khz as f64 / 1000.0)
+            };
+
+            let current = khz("scaling_cur_freq")
+                .or_else(|| khz("cpuinfo_cur_freq"))
+                .or_else(|| Some(cpu.frequency as f64).filter(|mhz| *mhz > 0.0));
+            let mut readings = Vec::new();
+            if let Some(mhz) = current {
+                readings.push(Sample::gauge("cpu.frequency", Unit::Megahertz, mhz));
+            }
+            if let Some(mhz) = khz("cpuinfo_min_freq") {
+                readings.push(Sample::gauge("cpu.frequency.min", Unit::Megahertz, mhz));
+            }
+            if let Some(mhz) = khz("cpuinfo_max_freq") {
+                readings.push(Sample::gauge("cpu.frequency.max", Unit::Megahertz, mhz));
+            }
+            samples.extend(
+                readings
+                    .into_iter()
+                    .map(|sample| sample.with_label("cpu", n.to_string())),
+            );
+        }
+
+        samples.extend(throttling(&cpus));
+        samples
+    }
+}
+
+/// Thermal throttle events per core, and per package, which every core of
+/// the package repeats and so is reported once.
+fn throttling(cpus: &BTreeMap<usize, PathBuf>) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    let mut packages = HashSet::new();
+
+    for (n, dir) in cpus {
+        let throttle = dir.join("thermal_throttle");
+        if let Some(count) = procfs::read_u64(&throttle.join("core_throttle_count")) {
+            samples.push(
+                Sample::counter("cpu.throttle.core", Unit::None, count as f64)
+                    .with_label("cpu", n.to_string()),
+            );
+        }
+
+        let package = procfs::read_topology_id(&dir.join("topology/physical_package_id"));
+        if let (Some(package), Some(count)) = (
+            package,
+            procfs::read_u64(&throttle.join("package_throttle_count")),
+        ) {
+            if packages.insert(package) {
+                samples.push(
+                    Sample::counter("cpu.throttle.package", Unit::None, count as f64)
+                        .with_label("package", package.to_string()),
+                );
+            }
+        }
+    }
+
+    samples
+}
@@ -0,0 +1,189 @@
+//! Per-mount filesystem usage.
+//!
+//! Space comes from the backend's disk list. Inode counts and the read-only flag
+//! come from `statvfs(3)` where the platform has it, with `/proc/mounts` as
+//! the fallback for the read-only flag.
+
+use crate::backend::{statvfs, Backend, Refresh};
+use crate::collectors::{matches_any, Collector};
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Which filesystems to report. Patterns may contain `*` wildcards; an empty
+/// include list includes everything.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiskConfig {
+    pub include_mounts: Vec<String>,
+    pub exclude_mounts: Vec<String>,
+    pub include_fs_types: Vec<String>,
+    pub exclude_fs_types: Vec<String>,
+}
+
+impl Default for DiskConfig {
+    fn default() -> Self {
+        DiskConfig {
+            include_mounts: Vec::new(),
+            exclude_mounts: Vec::new(),
+            include_fs_types: Vec::new(),
+            exclude_fs_types: ["tmpfs", "devtmpfs", "overlay", "squashfs", "nsfs"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl DiskConfig {
+    fn wants(&self, mount: &str, fs_type: &str) -> bool {
+        (self.include_mounts.is_empty() || matches_any(&self.include_mounts, mount))
+            && !matches_any(&self.exclude_mounts, mount)
+            && (self.include_fs_types.is_empty() || matches_any(&self.include_fs_types, fs_type))
+            && !matches_any(&self.exclude_fs_types, fs_type)
+    }
+}
+
+pub struct DiskCollector {
+    config: DiskConfig,
+    /// Whether the WASI gap has been logged.
+    warned: bool,
+}
+
+impl DiskCollector {
+    pub fn new(config: DiskConfig) -> Self {
+        DiskCollector {
+            config,
+            warned: false,
+        }
+    }
+}
+
+impl Collector for DiskCollector {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    // Filesystems fill up slowly
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(60))
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            disks: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        // WASI has no `statvfs(3)` or anything else that sizes a filesystem,
+        // so the backend lists no disks there
+        if cfg!(target_os = "wasi") {
+            if !self.warned {
+                eprintln!("WASI cannot tell how full a filesystem is, so the disk collector reports nothing");
+                self.warned = true;
+            }
+            return Vec::new();
+        }
+
+        let mut samples = Vec::new();
+        let read_only_mounts = read_only_mounts();
+
+        for disk in backend.disks() {
+            let mount = disk.mount_point.to_string_lossy().into_owned();
+            let fs_type = disk.fs_type;
+            if !self.config.wants(&mount, &fs_type) {
+                continue;
+            }
+            let device = disk.device;
+            let fs = statvfs(&disk.mount_point);
+
+            let total = disk.total;
+            let available = disk.available;
+            // Blocks reserved for root are neither available nor used
+            let used = match &fs {
+                Some(fs) => fs.total_bytes.saturating_sub(fs.free_bytes),
+                None => total.saturating_sub(available),
+            };
+            let read_only = match &fs {
+                Some(fs) => fs.read_only,
+                None => read_only_mounts.contains(&mount),
+            };
+
+            let series = |sample: Sample| {
+                sample
+                    .with_label("mount", mount.clone())
+                    .with_label("device", device.clone())
+                    .with_label("fs_type", fs_type.clone())
+            };
+            samples.push(series(Sample::gauge("filesystem.total", Unit::Bytes, total as f64)));
+            samples.push(series(Sample::gauge("filesystem.used", Unit::Bytes, used as f64)));
+            samples.push(series(Sample::gauge(
+                "filesystem.available",
+                Unit::Bytes,
+                available as f64,
+            )));
+            samples.push(series(Sample::gauge(
+                "filesystem.read_only",
+                Unit::None,
+                if read_only { 1.0 } else { 0.0 },
+            )));
+
+            // Some filesystems (e.g. FAT, btrfs) report no inodes at all
+            if let Some(fs) = fs.filter(|fs| fs.inodes_total > 0) {
+                let inodes_used = fs.inodes_total.saturating_sub(fs.inodes_free);
+                samples.push(series(Sample::gauge(
+                    "filesystem.inodes.total",
+                    Unit::None,
+                    fs.inodes_total as f64,
+                )));
+                samples.push(series(Sample::gauge(
+                    "filesystem.inodes.used",
+                    Unit::None,
+                    inodes_used as f64,
+                )));
+                samples.push(series(Sample::gauge(
+                    "filesystem.inodes.free",
+                    Unit::None,
+                    fs.inodes_free as f64,
+                )));
+            }
+        }
+
+        samples
+    }
+}
+
+/// Mount points listed with the `ro` option in `/proc/mounts`.
+fn read_only_mounts() -> HashSet<String> {
+    let mounts = match procfs::read_proc("mounts") {
+        Ok(mounts) => mounts,
+        Err(_) => return HashSet::new(),
+    };
+
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let mount = fields.get(1)?;
+            let options = fields.get(3)?;
+            if options.split(',').any(|o| o == "ro") {
+                Some(unescape_mount(mount))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Undoes the octal escaping `/proc/mounts` applies to spaces and tabs.
+fn unescape_mount(mount: &str) -> String {
+    mount
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
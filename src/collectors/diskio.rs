@@ -0,0 +1,384 @@
+//! Block device throughput and latency from `/proc/diskstats`.
+//!
+//! sysinfo has no per-device I/O counters, so this reads the kernel's
+//! cumulative counters directly and reports rates over the time between two
+//! collections.
+
+use crate::backend::Backend;
+use crate::collectors::{matches_any, Collector};
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::Instant;
+
+/// `/proc/diskstats` always counts in 512-byte sectors, whatever the device.
+const SECTOR_SIZE: f64 = 512.0;
+
+/// Which block devices to report. Patterns may contain `*` wildcards; an
+/// empty include list includes everything.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiskIoConfig {
+    /// Also report partitions, whose I/O is already counted in their disk's.
+    pub include_partitions: bool,
+    pub include_devices: Vec<String>,
+    pub exclude_devices: Vec<String>,
+}
+
+impl Default for DiskIoConfig {
+    fn default() -> Self {
+        DiskIoConfig {
+            include_partitions: false,
+            include_devices: Vec::new(),
+            exclude_devices: vec!["loop*".to_string(), "ram*".to_string()],
+        }
+    }
+}
+
+/// The cumulative counters of one line of `/proc/diskstats`.
+#[derive(Debug, Clone, Copy)]
+struct DiskStats {
+    reads: u64,
+    sectors_read: u64,
+    read_ms: u64,
+    writes: u64,
+    sectors_written: u64,
+    write_ms: u64,
+    in_flight: u64,
+    io_ms: u64,
+    weighted_io_ms: u64,
+}
+
+pub struct DiskIoCollector {
+    config: DiskIoConfig,
+    previous: HashMap<String, DiskStats>,
+    previous_at: Option<Instant>,
+}
+
+impl DiskIoCollector {
+    pub fn new(config: DiskIoConfig) -> Self {
+        DiskIoCollector {
+            config,
+            previous: HashMap::new(),
+            previous_at: None,
+        }
+    }
+
+    /// Reports the wanted devices of a `/proc/diskstats` read at `now`, with
+    /// rates since the previous read. Only devices in `disks` are reported
+    /// when it is given.
+    fn observe(
+        &mut self,
+        text: &str,
+        disks: Option<&HashSet<String>>,
+        now: Instant,
+    ) -> Vec<Sample> {
+        let elapsed = self
+            .previous_at
+            .map(|at| now.duration_since(at).as_secs_f64());
+
+        let mut samples = Vec::new();
+        let mut current = HashMap::new();
+        for (device, stats) in parse_diskstats(text) {
+            let wanted = disks.is_none_or(|disks| disks.contains(&device))
+                && (self.config.include_devices.is_empty()
+                    || matches_any(&self.config.include_devices, &device))
+                && !matches_any(&self.config.exclude_devices, &device);
+            if !wanted {
+                continue;
+            }
+
+            samples.push(
+                Sample::gauge("diskio.in_flight", Unit::None, stats.in_flight as f64)
+                    .with_label("device", device.clone()),
+            );
+            if let (Some(previous), Some(elapsed)) = (self.previous.get(&device), elapsed) {
+                if elapsed > 0.0 {
+                    samples.extend(rates(&device, previous, &stats, elapsed));
+                }
+            }
+            current.insert(device, stats);
+        }
+
+        self.previous = current;
+        self.previous_at = Some(now);
+        samples
+    }
+}
+
+impl Collector for DiskIoCollector {
+    fn name(&self) -> &'static str {
+        "diskio"
+    }
+
+    fn collect(&mut self, _backend: &dyn Backend) -> Vec<Sample> {
+        let text = match procfs::read_proc("diskstats") {
+            Ok(text) => text,
+            Err(_) => return Vec::new(),
+        };
+        let disks = if self.config.include_partitions {
+            None
+        } else {
+            whole_disks()
+        };
+        self.observe(&text, disks.as_ref(), Instant::now())
+    }
+}
+
+/// The whole disks, as listed in `/sys/block`; partitions only appear under
+/// their disk there. `None` when `/sys` is not available.
+fn whole_disks() -> Option<HashSet<String>> {
+    let entries = fs::read_dir(procfs::sys_path("block")).ok()?;
+    Some(
+        entries
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect(),
+    )
+}
+
+/// Rates and averages between two readings taken `elapsed` seconds apart.
+///
+/// Nothing is reported when a counter went backwards: the device was removed
+/// and another took its name, or a 32-bit counter wrapped, and the difference
+/// means nothing either way.
+fn rates(device: &str, before: &DiskStats, now: &DiskStats, elapsed: f64) -> Vec<Sample> {
+    let counters = |s: &DiskStats| {
+        [
+            s.reads,
+            s.sectors_read,
+            s.read_ms,
+            s.writes,
+            s.sectors_written,
+            s.write_ms,
+            s.io_ms,
+            s.weighted_io_ms,
+        ]
+    };
+    if counters(now)
+        .iter()
+        .zip(counters(before))
+        .any(|(now, before)| *now < before)
+    {
+        return Vec::new();
+    }
+
+    let delta = |now: u64, before: u64| now.saturating_sub(before) as f64;
+    let reads = delta(now.reads, before.reads);
+    let writes = delta(now.writes, before.writes);
+    let ios = reads + writes;
+    let io_ms = delta(now.io_ms, before.io_ms);
+    let elapsed_ms = elapsed * 1000.0;
+
+    let mut samples = vec![
+        Sample::gauge(
+            "diskio.read_bytes",
+            Unit::BytesPerSecond,
+            delta(now.sectors_read, before.sectors_read) * SECTOR_SIZE / elapsed,
+        ),
+        Sample::gauge(
+            "diskio.write_bytes",
+            Unit::BytesPerSecond,
+            delta(now.sectors_written, before.sectors_written) * SECTOR_SIZE / elapsed,
+        ),
+        Sample::gauge("diskio.read_iops", Unit::PerSecond, reads / elapsed),
+        Sample::gauge("diskio.write_iops", Unit::PerSecond, writes / elapsed),
+        // Average number of requests queued or in service, like iostat's aqu-sz
+        Sample::gauge(
+            "diskio.queue_depth",
+            Unit::None,
+            delta(now.weighted_io_ms, before.weighted_io_ms) / elapsed_ms,
+        ),
+        Sample::gauge(
+            "diskio.utilization",
+            Unit::Percent,
+            (io_ms * 100.0 / elapsed_ms).min(100.0),
+        ),
+    ];
+
+    // Latencies are undefined when the device was idle
+    if ios > 0.0 {
+        samples.push(Sample::gauge(
+            "diskio.service_time",
+            Unit::Milliseconds,
+            io_ms / ios,
+        ));
+        samples.push(Sample::gauge(
+            "diskio.await",
+            Unit::Milliseconds,
+            (delta(now.read_ms, before.read_ms) + delta(now.write_ms, before.write_ms)) / ios,
+        ));
+    }
+
+    samples
+        .into_iter()
+        .map(|s| s.with_label("device", device))
+        .collect()
+}
+
+fn parse_diskstats(text: &str) -> Vec<(String, DiskStats)> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 14 {
+                return None;
+            }
+            let num = |i: usize| fields[i].parse::<u64>().unwrap_or(0);
+            Some((
+                fields[2].to_string(),
+                DiskStats {
+                    reads: num(3),
+                    sectors_read: num(5),
+                    read_ms: num(6),
+                    writes: num(7),
+                    sectors_written: num(9),
+                    write_ms: num(10),
+                    in_flight: num(11),
+                    io_ms: num(12),
+                    weighted_io_ms: num(13),
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// `/proc/diskstats` with `sda` and its partition, and a loop device.
+    fn diskstats(reads: u64, sectors_read: u64, writes: u64, sectors_written: u64) -> String {
+        format!(
+            "7 0 loop0 52 0 2138 12 0 0 0 0 0 24 12 0 0 0 0 0 0\n\
+             8 0 sda {r} 120 {sr} 300 {w} 80 {sw} 500 2 900 1200 0 0 0 0 0 0\n\
+             8 1 sda1 {r} 120 {sr} 300 {w} 80 {sw} 500 0 900 1200 0 0 0 0\n",
+            r = reads,
+            sr = sectors_read,
+            w = writes,
+            sw = sectors_written,
+        )
+    }
+
+    fn disks() -> HashSet<String> {
+        ["loop0", "sda"].iter().map(|d| d.to_string()).collect()
+    }
+
+    fn value(samples: &[Sample], name: &str, device: &str) -> Option<f64> {
+        samples
+            .iter()
+            .find(|s| s.name == name && s.labels["device"] == device)
+            .and_then(|s| s.scalar_value())
+    }
+
+    fn devices(samples: &[Sample]) -> HashSet<&str> {
+        samples
+            .iter()
+            .map(|s| s.labels["device"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_diskstats_lines() {
+        let stats = parse_diskstats(&diskstats(1000, 8000, 400, 3200));
+        let names: Vec<&str> = stats.iter().map(|(d, _)| d.as_str()).collect();
+        assert_eq!(names, vec!["loop0", "sda", "sda1"]);
+
+        let (_, sda) = &stats[1];
+        assert_eq!(
+            (sda.reads, sda.sectors_read, sda.read_ms),
+            (1000, 8000, 300)
+        );
+        assert_eq!(
+            (sda.writes, sda.sectors_written, sda.write_ms),
+            (400, 3200, 500)
+        );
+        assert_eq!(
+            (sda.in_flight, sda.io_ms, sda.weighted_io_ms),
+            (2, 900, 1200)
+        );
+
+        // Short lines are not device lines
+        assert!(parse_diskstats("8 0 sda 1 2 3\n").is_empty());
+    }
+
+    #[test]
+    fn leaves_out_partitions_and_excluded_devices() {
+        let mut collector = DiskIoCollector::new(DiskIoConfig::default());
+        let text = diskstats(1000, 8000, 400, 3200);
+        let samples = collector.observe(&text, Some(&disks()), Instant::now());
+        assert_eq!(devices(&samples), HashSet::from(["sda"]));
+
+        // Without /sys every device is reported
+        let samples = collector.observe(&text, None, Instant::now());
+        assert_eq!(devices(&samples), HashSet::from(["sda", "sda1"]));
+    }
+
+    #[test]
+    fn reports_rates_from_the_second_read() {
+        let mut collector = DiskIoCollector::new(DiskIoConfig::default());
+        let start = Instant::now();
+        let first = collector.observe(&diskstats(1000, 8000, 400, 3200), Some(&disks()), start);
+        assert_eq!(value(&first, "diskio.read_bytes", "sda"), None);
+        assert_eq!(value(&first, "diskio.in_flight", "sda"), Some(2.0));
+
+        // 2 seconds later: 100 more reads of 800 sectors and 50 writes of 400
+        let later = start + Duration::from_secs(2);
+        let second = collector.observe(&diskstats(1100, 8800, 450, 3600), Some(&disks()), later);
+        assert_eq!(
+            value(&second, "diskio.read_bytes", "sda"),
+            Some(800.0 * 512.0 / 2.0)
+        );
+        assert_eq!(
+            value(&second, "diskio.write_bytes", "sda"),
+            Some(400.0 * 512.0 / 2.0)
+        );
+        assert_eq!(value(&second, "diskio.read_iops", "sda"), Some(50.0));
+        assert_eq!(value(&second, "diskio.write_iops", "sda"), Some(25.0));
+        // Idle otherwise: no time spent doing I/O, so no latency either
+        assert_eq!(value(&second, "diskio.utilization", "sda"), Some(0.0));
+        assert_eq!(value(&second, "diskio.service_time", "sda"), Some(0.0));
+    }
+
+    #[test]
+    fn skips_rates_when_counters_go_backwards() {
+        let mut collector = DiskIoCollector::new(DiskIoConfig::default());
+        let start = Instant::now();
+        collector.observe(&diskstats(1000, 8000, 400, 3200), Some(&disks()), start);
+        let reset = collector.observe(
+            &diskstats(10, 80, 4, 32),
+            Some(&disks()),
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(value(&reset, "diskio.read_bytes", "sda"), None);
+        assert_eq!(value(&reset, "diskio.in_flight", "sda"), Some(2.0));
+
+        // Rates resume against the reset counters
+        let after = collector.observe(
+            &diskstats(20, 160, 4, 32),
+            Some(&disks()),
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(value(&after, "diskio.read_iops", "sda"), Some(10.0));
+    }
+
+    #[test]
+    fn forgets_devices_that_disappear() {
+        let mut collector = DiskIoCollector::new(DiskIoConfig::default());
+        let start = Instant::now();
+        collector.observe(&diskstats(1000, 8000, 400, 3200), Some(&disks()), start);
+        let gone = collector.observe("", Some(&disks()), start + Duration::from_secs(1));
+        assert!(gone.is_empty());
+
+        // Back again, it starts over without a rate against the old counters
+        let back = collector.observe(
+            &diskstats(1100, 8800, 450, 3600),
+            Some(&disks()),
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(value(&back, "diskio.read_bytes", "sda"), None);
+        assert_eq!(value(&back, "diskio.in_flight", "sda"), Some(2.0));
+    }
+}
@@ -0,0 +1,101 @@
+//! What the host is made of: CPU sockets, cores and NUMA nodes, and the CPU
+//! model.
+//!
+//! The figures only change when hardware is added or CPUs are taken offline,
+//! so they are sent once an hour by default rather than with every batch.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use std::collections::HashSet;
+use std::fs;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct InventoryCollector;
+
+impl InventoryCollector {
+    pub fn new() -> Self {
+        InventoryCollector
+    }
+}
+
+impl Collector for InventoryCollector {
+    fn name(&self) -> &'static str {
+        "inventory"
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(3600))
+    }
+
+    // The CPU model, and the core count where sysfs has no topology
+    fn needs(&self) -> Refresh {
+        Refresh {
+            cpu: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let mut sockets = HashSet::new();
+        let mut cores = HashSet::new();
+        let mut threads = 0;
+        for dir in procfs::cpu_dirs().values() {
+            // Offline CPUs have no topology directory
+            let topology = dir.join("topology");
+            let package = match procfs::read_topology_id(&topology.join("physical_package_id")) {
+                Some(package) => package,
+                None => continue,
+            };
+            let die = procfs::read_topology_id(&topology.join("die_id")).unwrap_or(0);
+            let core = procfs::read_topology_id(&topology.join("core_id")).unwrap_or(0);
+            sockets.insert(package);
+            cores.insert((package, die, core));
+            threads += 1;
+        }
+
+        let (sockets, cores) = if threads == 0 {
+            // No sysfs: count what the backend can see and assume a single socket
+            threads = backend.cpus().len();
+            (1, backend.physical_core_count().unwrap_or(threads))
+        } else {
+            (sockets.len(), cores.len())
+        };
+
+        let cpu = backend.global_cpu();
+        vec![
+            Sample::gauge("cpu.topology.sockets", Unit::None, sockets as f64),
+            Sample::gauge("cpu.topology.cores", Unit::None, cores as f64),
+            Sample::gauge("cpu.topology.threads", Unit::None, threads as f64),
+            Sample::gauge(
+                "cpu.topology.numa_nodes",
+                Unit::None,
+                numa_nodes().max(1) as f64,
+            ),
+            Sample::gauge("cpu.info", Unit::None, 1.0)
+                .with_label("vendor", cpu.vendor)
+                .with_label("brand", cpu.brand),
+        ]
+    }
+}
+
+/// The `nodeN` directories of `/sys/devices/system/node`.
+fn numa_nodes() -> usize {
+    fs::read_dir(procfs::sys_path("devices/system/node"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| {
+                    let name = entry.file_name();
+                    let node = name.to_string_lossy();
+                    matches!(
+                        node.strip_prefix("node").map(str::parse::<usize>),
+                        Some(Ok(_))
+                    )
+                })
+                .count()
+        })
+        .unwrap_or(0)
+}
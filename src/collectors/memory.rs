@@ -0,0 +1,266 @@
+//! Memory and swap usage, paging activity and OOM kills.
+//!
+//! Totals come from the backend. The detailed breakdown comes from
+//! `/proc/meminfo`, and paging and OOM counters from `/proc/vmstat`, so they
+//! are only reported on Linux.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// `/proc/meminfo` fields reported as is, and the metric each one becomes.
+const MEMINFO_FIELDS: &[(&str, &str)] = &[
+    ("MemFree", "memory.free"),
+    ("Buffers", "memory.buffers"),
+    ("Cached", "memory.cached"),
+    ("Dirty", "memory.dirty"),
+    ("Writeback", "memory.writeback"),
+    ("Slab", "memory.slab"),
+    ("Shmem", "memory.shared"),
+];
+
+pub struct MemoryCollector {
+    page_size: u64,
+    /// Pages swapped in and out as of the previous collection.
+    previous_swap: Option<(u64, u64, Instant)>,
+}
+
+impl Default for MemoryCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryCollector {
+    pub fn new() -> Self {
+        MemoryCollector {
+            page_size: page_size(),
+            previous_swap: None,
+        }
+    }
+
+    fn paging(&mut self, vmstat: &HashMap<&str, u64>) -> Vec<Sample> {
+        let mut samples = Vec::new();
+
+        if let Some(faults) = vmstat.get("pgmajfault") {
+            samples.push(Sample::counter(
+                "memory.major_faults",
+                Unit::None,
+                *faults as f64,
+            ));
+        }
+        // Only kernels since 4.13 count OOM kills
+        if let Some(kills) = vmstat.get("oom_kill") {
+            samples.push(Sample::counter("memory.oom_kills", Unit::None, *kills as f64));
+        }
+
+        if let (Some(&swap_in), Some(&swap_out)) = (vmstat.get("pswpin"), vmstat.get("pswpout")) {
+            let now = Instant::now();
+            if let Some((prev_in, prev_out, at)) = self.previous_swap {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    let rate = |now: u64, before: u64| {
+                        (now.saturating_sub(before) * self.page_size) as f64 / elapsed
+                    };
+                    samples.push(Sample::gauge(
+                        "memory.swap.in",
+                        Unit::BytesPerSecond,
+                        rate(swap_in, prev_in),
+                    ));
+                    samples.push(Sample::gauge(
+                        "memory.swap.out",
+                        Unit::BytesPerSecond,
+                        rate(swap_out, prev_out),
+                    ));
+                }
+            }
+            self.previous_swap = Some((swap_in, swap_out, now));
+        }
+
+        samples
+    }
+}
+
+impl Collector for MemoryCollector {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            memory: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let memory = backend.memory();
+        let mut samples = vec![
+            Sample::gauge("memory.total", Unit::Bytes, memory.total as f64),
+            Sample::gauge("memory.available", Unit::Bytes, memory.available as f64),
+            Sample::gauge("memory.swap.total", Unit::Bytes, memory.swap_total as f64),
+            Sample::gauge("memory.swap.used", Unit::Bytes, memory.swap_used as f64),
+        ];
+
+        if let Ok(meminfo) = procfs::read_proc("meminfo") {
+            let meminfo = parse_meminfo(&meminfo);
+            for (field, name) in MEMINFO_FIELDS {
+                if let Some(bytes) = meminfo.get(*field) {
+                    samples.push(Sample::gauge(name, Unit::Bytes, *bytes as f64));
+                }
+            }
+        }
+
+        if let Ok(vmstat) = procfs::read_proc("vmstat") {
+            samples.extend(self.paging(&parse_vmstat(&vmstat)));
+        }
+
+        samples
+    }
+}
+
+/// Parses `/proc/meminfo` into bytes per field.
+fn parse_meminfo(text: &str) -> HashMap<&str, u64> {
+    text.lines()
+        .filter_map(|line| {
+            let (field, rest) = line.split_once(':')?;
+            let mut parts = rest.split_whitespace();
+            let value: u64 = parts.next()?.parse().ok()?;
+            // Everything with a unit is in KiB; the page counts have none
+            let bytes = match parts.next() {
+                Some("kB") => value * 1024,
+                _ => value,
+            };
+            Some((field, bytes))
+        })
+        .collect()
+}
+
+fn parse_vmstat(text: &str) -> HashMap<&str, u64> {
+    text.lines()
+        .filter_map(|line| {
+            let (field, value) = line.split_once(' ')?;
+            Some((field, value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn page_size() -> u64 {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> u64 {
+    4096
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMINFO: &str = "\
+MemTotal:       16318340 kB
+MemFree:         1210244 kB
+MemAvailable:    9876540 kB
+Buffers:          402312 kB
+Cached:          7654321 kB
+Dirty:               128 kB
+Writeback:             0 kB
+Shmem:            512000 kB
+HugePages_Total:       0
+Hugepagesize:       2048 kB
+";
+
+    const VMSTAT: &str = "\
+nr_free_pages 302561
+pgmajfault 4321
+pswpin 100
+pswpout 250
+oom_kill 2
+";
+
+    #[test]
+    fn converts_meminfo_to_bytes() {
+        let meminfo = parse_meminfo(MEMINFO);
+        assert_eq!(meminfo["MemTotal"], 16_318_340 * 1024);
+        assert_eq!(meminfo["Buffers"], 402_312 * 1024);
+        assert_eq!(meminfo["Writeback"], 0);
+        // Counts without a unit are kept as they are
+        assert_eq!(meminfo["HugePages_Total"], 0);
+        assert_eq!(meminfo["Hugepagesize"], 2048 * 1024);
+    }
+
+    #[test]
+    fn leaves_out_missing_meminfo_fields() {
+        // Slab is missing from the fixture, as on kernels without it
+        let meminfo = parse_meminfo(MEMINFO);
+        assert_eq!(meminfo.get("Slab"), None);
+        assert!(parse_meminfo("").is_empty());
+        assert!(parse_meminfo("Broken: lots kB\nNoColon 12 kB\n").is_empty());
+    }
+
+    #[test]
+    fn parses_vmstat() {
+        let vmstat = parse_vmstat(VMSTAT);
+        assert_eq!(vmstat["pgmajfault"], 4321);
+        assert_eq!(vmstat["oom_kill"], 2);
+        assert_eq!(vmstat.len(), 5);
+    }
+
+    fn names(samples: &[Sample]) -> Vec<&str> {
+        samples.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn reports_swap_rates_from_the_second_collection() {
+        let mut collector = MemoryCollector {
+            page_size: 4096,
+            previous_swap: None,
+        };
+        let vmstat = parse_vmstat(VMSTAT);
+        assert_eq!(
+            names(&collector.paging(&vmstat)),
+            vec!["memory.major_faults", "memory.oom_kills"]
+        );
+
+        // Rewind the previous reading so that time has passed since
+        let (swap_in, swap_out, at) = collector.previous_swap.unwrap();
+        let at = at - std::time::Duration::from_secs(2);
+        collector.previous_swap = Some((swap_in - 10, swap_out, at));
+        let samples = collector.paging(&vmstat);
+        let rate = |name: &str| {
+            samples
+                .iter()
+                .find(|s| s.name == name)
+                .and_then(|s| s.scalar_value())
+                .unwrap()
+        };
+        // 10 pages of 4 KiB in a little over 2 seconds
+        assert!(rate("memory.swap.in") > 0.0 && rate("memory.swap.in") <= 10.0 * 4096.0 / 2.0);
+        assert_eq!(rate("memory.swap.out"), 0.0);
+    }
+
+    #[test]
+    fn leaves_out_missing_vmstat_counters() {
+        let mut collector = MemoryCollector {
+            page_size: 4096,
+            previous_swap: None,
+        };
+        // Kernels before 4.13 have no oom_kill, and without swap support no
+        // pswpin or pswpout
+        let vmstat = parse_vmstat("pgmajfault 7\n");
+        assert_eq!(
+            names(&collector.paging(&vmstat)),
+            vec!["memory.major_faults"]
+        );
+        assert_eq!(collector.previous_swap, None);
+    }
+}
@@ -0,0 +1,216 @@
+//! Collectors turn what the host exposes into [`Sample`](crate::Sample)s.
+//!
+//! Collectors that report rates keep the previous reading and compute deltas
+//! between two calls, so their first call returns only the absolute values.
+//!
+//! Every collector sits behind a Cargo feature of the same name, all on by
+//! default, so that WASI and embedded builds can leave out what they do not
+//! need. The [`Registry`] builds the ones compiled in and enabled by the
+//! config.
+
+use crate::backend::{Backend, Refresh};
+use crate::metrics::Sample;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(feature = "agent")]
+pub mod agent;
+#[cfg(feature = "cgroup")]
+pub mod cgroup;
+#[cfg(feature = "cpu")]
+pub mod cpu;
+#[cfg(feature = "cpufreq")]
+pub mod cpufreq;
+#[cfg(feature = "disk")]
+pub mod disk;
+#[cfg(feature = "diskio")]
+pub mod diskio;
+#[cfg(feature = "inventory")]
+pub mod inventory;
+#[cfg(feature = "memory")]
+pub mod memory;
+#[cfg(feature = "network")]
+pub mod network;
+#[cfg(feature = "process")]
+pub mod process;
+#[cfg(feature = "psi")]
+pub mod psi;
+mod registry;
+#[cfg(feature = "sensors")]
+pub mod sensors;
+#[cfg(feature = "sockets")]
+pub mod sockets;
+#[cfg(feature = "system")]
+pub mod system;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+pub use registry::{available, Registry};
+
+/// A source of samples.
+pub trait Collector {
+    /// Identifies the collector in the config and its Cargo feature, e.g.
+    /// `cpu` or `diskio`.
+    fn name(&self) -> &'static str;
+
+    /// How often the collector would like to run when the config does not
+    /// say; `None` follows the agent's interval.
+    fn interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The parts of the backend [`collect`](Collector::collect) reads, which
+    /// are refreshed right before it runs.
+    fn needs(&self) -> Refresh {
+        Refresh::default()
+    }
+
+    /// Reads the collector's samples. Figures common to several collectors
+    /// come from `backend`, which the caller has refreshed.
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample>;
+}
+
+/// Per-collector settings, the `[collectors]` section of the agent config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorsConfig {
+    /// Collectors to run; empty runs every one in the build.
+    pub enabled: Vec<String>,
+    /// Collectors to leave out.
+    pub disabled: Vec<String>,
+    /// Seconds between two runs of a collector, by name, overriding the
+    /// agent's interval.
+    pub intervals: BTreeMap<String, u64>,
+    /// Runs collectors on multiples of their interval since the Unix epoch,
+    /// e.g. on the minute for a 60 second interval, rather than counting
+    /// from when the agent started.
+    pub align: bool,
+    /// Shifts the aligned schedule by a random amount up to this many
+    /// milliseconds, drawn once at start, so a fleet of agents does not hit
+    /// shared storage or the server all at once.
+    pub jitter_ms: u64,
+    #[cfg(feature = "cgroup")]
+    pub cgroup: cgroup::CgroupConfig,
+    #[cfg(feature = "disk")]
+    pub disk: disk::DiskConfig,
+    #[cfg(feature = "diskio")]
+    pub diskio: diskio::DiskIoConfig,
+    #[cfg(feature = "network")]
+    pub network: network::NetworkConfig,
+    #[cfg(feature = "process")]
+    pub process: process::ProcessConfig,
+    /// Processes whose liveness is tracked, the `[[collectors.watch]]` entries.
+    #[cfg(feature = "watch")]
+    pub watch: Vec<watch::WatchConfig>,
+}
+
+impl Default for CollectorsConfig {
+    fn default() -> Self {
+        CollectorsConfig {
+            enabled: Vec::new(),
+            disabled: Vec::new(),
+            intervals: BTreeMap::new(),
+            align: true,
+            jitter_ms: 0,
+            #[cfg(feature = "cgroup")]
+            cgroup: Default::default(),
+            #[cfg(feature = "disk")]
+            disk: Default::default(),
+            #[cfg(feature = "diskio")]
+            diskio: Default::default(),
+            #[cfg(feature = "network")]
+            network: Default::default(),
+            #[cfg(feature = "process")]
+            process: Default::default(),
+            #[cfg(feature = "watch")]
+            watch: Vec::new(),
+        }
+    }
+}
+
+impl CollectorsConfig {
+    /// Whether the collector called `name` should run.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        (self.enabled.is_empty() || self.enabled.iter().any(|n| n == name))
+            && !self.disabled.iter().any(|n| n == name)
+    }
+}
+
+/// Matches `value` against a pattern where `*` stands for any run of
+/// characters, e.g. `/run/*` or `veth*`.
+pub fn matches_glob(pattern: &str, value: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == value;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if value.len() < first.len() + last.len()
+        || !value.starts_with(first)
+        || !value.ends_with(last)
+    {
+        return false;
+    }
+    let mut rest = &value[first.len()..value.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Whether any of `patterns` matches `value`.
+pub fn matches_any(patterns: &[String], value: &str) -> bool {
+    patterns.iter().any(|p| matches_glob(p, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        let cases = [
+            ("/", "/", true),
+            ("/", "/home", false),
+            ("/run/*", "/run/user/1000", true),
+            ("/run/*", "/run", false),
+            ("/run/*", "/run/", true),
+            ("veth*", "veth0a1b", true),
+            ("veth*", "eth0", false),
+            ("*", "", true),
+            ("*", "anything", true),
+            ("*.img", "/var/disk.img", true),
+            ("*.img", "/var/disk.iso", false),
+            ("/snap/*/common", "/snap/core/common", true),
+            ("/snap/*/common", "/snap/core/current", false),
+            ("a*b*c", "abc", true),
+            ("a*b*c", "a-c-b-c", true),
+            ("a*b*c", "acb", false),
+            // The prefix and suffix must not overlap
+            ("ab*ba", "aba", false),
+            ("**", "x", true),
+        ];
+        for (pattern, value, expected) in cases {
+            assert_eq!(
+                matches_glob(pattern, value),
+                expected,
+                "{:?} against {:?}",
+                pattern,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn matches_any_pattern() {
+        let patterns = vec!["lo".to_string(), "docker*".to_string()];
+        assert!(matches_any(&patterns, "lo"));
+        assert!(matches_any(&patterns, "docker0"));
+        assert!(!matches_any(&patterns, "eth0"));
+        assert!(!matches_any(&[], "eth0"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Per-interface traffic, error and drop counters.
+//!
+//! Counters come from the backend's network list. Neither backend counts
+//! drops, so those are taken from `/proc/net/dev`, which is also used for
+//! everything when the backend finds no interfaces. Each counter is reported
+//! as is and as a rate over the time since the previous collection.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::{matches_any, Collector};
+use crate::metrics::{Sample, Unit};
+use crate::procfs::{self, NetDev};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+/// `ARPHRD_LOOPBACK` from `<linux/if_arp.h>`, as found in `/sys/class/net/*/type`.
+const ARPHRD_LOOPBACK: &str = "772";
+
+/// Which interfaces to report. Patterns may contain `*` wildcards; an empty
+/// include list includes everything.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub include_loopback: bool,
+    /// Virtual interfaces are bridges, veth pairs, tunnels and the like:
+    /// anything not backed by a physical device.
+    pub include_virtual: bool,
+    pub include_interfaces: Vec<String>,
+    pub exclude_interfaces: Vec<String>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            include_loopback: false,
+            include_virtual: true,
+            include_interfaces: Vec::new(),
+            exclude_interfaces: Vec::new(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    fn wants(&self, interface: &str) -> bool {
+        (self.include_interfaces.is_empty() || matches_any(&self.include_interfaces, interface))
+            && !matches_any(&self.exclude_interfaces, interface)
+            && (self.include_loopback || !is_loopback(interface))
+            && (self.include_virtual || !is_virtual(interface))
+    }
+}
+
+/// Cumulative counters of one interface, in the order they are reported.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    rx_bytes: u64,
+    rx_packets: u64,
+    rx_errors: u64,
+    rx_drops: Option<u64>,
+    tx_bytes: u64,
+    tx_packets: u64,
+    tx_errors: u64,
+    tx_drops: Option<u64>,
+}
+
+impl Counters {
+    /// Name, unit and value of every counter that is known.
+    fn values(&self) -> Vec<(&'static str, Unit, u64)> {
+        let mut values = vec![
+            ("network.rx_bytes", Unit::Bytes, self.rx_bytes),
+            ("network.rx_packets", Unit::None, self.rx_packets),
+            ("network.rx_errors", Unit::None, self.rx_errors),
+            ("network.tx_bytes", Unit::Bytes, self.tx_bytes),
+            ("network.tx_packets", Unit::None, self.tx_packets),
+            ("network.tx_errors", Unit::None, self.tx_errors),
+        ];
+        if let Some(drops) = self.rx_drops {
+            values.push(("network.rx_drops", Unit::None, drops));
+        }
+        if let Some(drops) = self.tx_drops {
+            values.push(("network.tx_drops", Unit::None, drops));
+        }
+        values
+    }
+}
+
+pub struct NetworkCollector {
+    config: NetworkConfig,
+    previous: HashMap<String, Counters>,
+    previous_at: Option<Instant>,
+}
+
+impl NetworkCollector {
+    pub fn new(config: NetworkConfig) -> Self {
+        NetworkCollector {
+            config,
+            previous: HashMap::new(),
+            previous_at: None,
+        }
+    }
+}
+
+impl Collector for NetworkCollector {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            networks: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let now = Instant::now();
+        let elapsed = self.previous_at.map(|at| now.duration_since(at).as_secs_f64());
+
+        let mut samples = Vec::new();
+        let mut current = HashMap::new();
+        for (interface, counters) in read_counters(backend) {
+            if !self.config.wants(&interface) {
+                continue;
+            }
+
+            let values = counters.values();
+            for (name, unit, value) in &values {
+                samples.push(
+                    Sample::counter(name, *unit, *value as f64)
+                        .with_label("interface", interface.clone()),
+                );
+            }
+
+            if let (Some(previous), Some(elapsed)) = (self.previous.get(&interface), elapsed) {
+                let before: HashMap<&str, u64> =
+                    previous.values().into_iter().map(|(n, _, v)| (n, v)).collect();
+                for (name, unit, value) in &values {
+                    let (before, rate_unit) = match (before.get(name), unit) {
+                        (Some(before), Unit::Bytes) => (*before, Unit::BytesPerSecond),
+                        (Some(before), _) => (*before, Unit::PerSecond),
+                        (None, _) => continue,
+                    };
+                    if elapsed > 0.0 {
+                        samples.push(
+                            Sample::gauge(
+                                &format!("{}.rate", name),
+                                rate_unit,
+                                value.saturating_sub(before) as f64 / elapsed,
+                            )
+                            .with_label("interface", interface.clone()),
+                        );
+                    }
+                }
+            }
+            current.insert(interface, counters);
+        }
+
+        self.previous = current;
+        self.previous_at = Some(now);
+        samples
+    }
+}
+
+/// Counters of every interface, preferring the backend and filling in what
+/// it lacks from `/proc/net/dev`.
+fn read_counters(backend: &dyn Backend) -> Vec<(String, Counters)> {
+    let proc_dev: HashMap<String, NetDev> = procfs::read_proc("net/dev")
+        .map(|text| procfs::parse_net_dev(&text))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|dev| (dev.name.clone(), dev))
+        .collect();
+
+    let mut interfaces: Vec<(String, Counters)> = backend
+        .networks()
+        .into_iter()
+        .map(|network| {
+            let drops = proc_dev.get(network.name.as_str());
+            let counters = Counters {
+                rx_bytes: network.rx_bytes,
+                rx_packets: network.rx_packets,
+                rx_errors: network.rx_errors,
+                rx_drops: drops.map(|dev| dev.rx_drops),
+                tx_bytes: network.tx_bytes,
+                tx_packets: network.tx_packets,
+                tx_errors: network.tx_errors,
+                tx_drops: drops.map(|dev| dev.tx_drops),
+            };
+            (network.name, counters)
+        })
+        .collect();
+
+    if interfaces.is_empty() {
+        interfaces = proc_dev
+            .into_iter()
+            .map(|(name, dev)| (name, dev.into()))
+            .collect();
+    }
+    interfaces.sort_by(|a, b| a.0.cmp(&b.0));
+    interfaces
+}
+
+impl From<NetDev> for Counters {
+    fn from(dev: NetDev) -> Self {
+        Counters {
+            rx_bytes: dev.rx_bytes,
+            rx_packets: dev.rx_packets,
+            rx_errors: dev.rx_errors,
+            rx_drops: Some(dev.rx_drops),
+            tx_bytes: dev.tx_bytes,
+            tx_packets: dev.tx_packets,
+            tx_errors: dev.tx_errors,
+            tx_drops: Some(dev.tx_drops),
+        }
+    }
+}
+
+fn is_loopback(interface: &str) -> bool {
+    match procfs::read_sys(&format!("class/net/{}/type", interface)) {
+        Ok(kind) => kind.trim() == ARPHRD_LOOPBACK,
+        Err(_) => interface == "lo",
+    }
+}
+
+/// Physical interfaces link to their bus device; virtual ones live under
+/// `/sys/devices/virtual`.
+fn is_virtual(interface: &str) -> bool {
+    fs::read_link(procfs::sys_path(&format!("class/net/{}", interface)))
+        .map(|target| target.to_string_lossy().contains("/virtual/"))
+        .unwrap_or(false)
+}
@@ -0,0 +1,221 @@
+//! The busiest processes, ranked by CPU, memory or disk I/O.
+//!
+//! The process table is the one the backend already refreshes. The owning user
+//! and thread count are read from `/proc/<pid>/status`, so they are only
+//! reported on Linux.
+
+use crate::backend::{Backend, Process, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// What to rank processes by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Cpu,
+    Memory,
+    /// Bytes read since the previous collection.
+    DiskRead,
+    /// Bytes written since the previous collection.
+    DiskWrite,
+}
+
+impl SortKey {
+    /// `moved` is what the process read and wrote since the previous
+    /// collection.
+    fn value(&self, process: &Process, moved: (u64, u64)) -> f64 {
+        match self {
+            SortKey::Cpu => process.cpu_usage as f64,
+            SortKey::Memory => process.memory as f64,
+            SortKey::DiskRead => moved.0 as f64,
+            SortKey::DiskWrite => moved.1 as f64,
+        }
+    }
+
+    fn is_disk(&self) -> bool {
+        matches!(self, SortKey::DiskRead | SortKey::DiskWrite)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessConfig {
+    /// How many processes to report for each sort key.
+    pub top_n: usize,
+    /// The top `top_n` by each key are reported; a process in several lists
+    /// is reported once.
+    pub sort_by: Vec<SortKey>,
+    /// Report only the program in the `cmdline` label, not its arguments,
+    /// which often carry secrets.
+    pub redact_args: bool,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        ProcessConfig {
+            top_n: 5,
+            sort_by: vec![SortKey::Cpu, SortKey::Memory],
+            redact_args: true,
+        }
+    }
+}
+
+pub struct ProcessCollector {
+    config: ProcessConfig,
+    /// Bytes every process had read and written as of the previous
+    /// collection, by pid and start time so a reused pid starts afresh.
+    previous: Option<HashMap<(u32, u64), (u64, u64)>>,
+}
+
+impl ProcessCollector {
+    pub fn new(config: ProcessConfig) -> Self {
+        ProcessCollector {
+            config,
+            previous: None,
+        }
+    }
+
+    fn describe(&self, process: &Process, users: &HashMap<String, String>) -> Vec<Sample> {
+        let pid = process.pid.to_string();
+        let status = procfs::read_proc(&format!("{}/status", pid)).ok();
+        let status_field = |name: &str| -> Option<String> {
+            status.as_deref()?.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                // Uid lists real, effective, saved and fs ids; the real one comes first
+                value.split_whitespace().next().map(str::to_string)
+            })
+        };
+
+        let cmdline = match process.cmd.split_first() {
+            Some((program, args)) if !self.config.redact_args && !args.is_empty() => {
+                format!("{} {}", program, args.join(" "))
+            }
+            Some((program, _)) => program.clone(),
+            None => process.name.clone(),
+        };
+        let user = status_field("Uid").map(|uid| users.get(&uid).cloned().unwrap_or(uid));
+
+        // CPU usage is per core, so a busy multi-threaded process can
+        // exceed 100%
+        let mut samples = vec![
+            Sample::gauge("process.cpu", Unit::Percent, process.cpu_usage as f64),
+            Sample::gauge("process.memory.rss", Unit::Bytes, process.memory as f64),
+            Sample::counter(
+                "process.disk.read_bytes",
+                Unit::Bytes,
+                process.read_bytes as f64,
+            ),
+            Sample::counter(
+                "process.disk.written_bytes",
+                Unit::Bytes,
+                process.written_bytes as f64,
+            ),
+        ];
+        if let Some(threads) = status_field("Threads").and_then(|t| t.parse::<f64>().ok()) {
+            samples.push(Sample::gauge("process.threads", Unit::None, threads));
+        }
+
+        samples
+            .into_iter()
+            .map(|sample| {
+                let sample = sample
+                    .with_label("pid", pid.clone())
+                    .with_label("name", process.name.clone())
+                    .with_label("cmdline", cmdline.clone());
+                match &user {
+                    Some(user) => sample.with_label("user", user.clone()),
+                    None => sample,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Collector for ProcessCollector {
+    fn name(&self) -> &'static str {
+        "process"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            processes: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let all = backend.processes();
+        let processes: Vec<&Process> = all.iter().collect();
+
+        // Lifetime totals would rank a long-running process that once read a
+        // lot above one busy right now. A process not seen before started
+        // since the previous collection, so all its I/O counts.
+        let previous = self.previous.take();
+        let moved: HashMap<u32, (u64, u64)> = all
+            .iter()
+            .map(|p| {
+                let (read, written) = previous
+                    .as_ref()
+                    .and_then(|previous| previous.get(&(p.pid, p.start_time)))
+                    .copied()
+                    .unwrap_or((0, 0));
+                let moved = (
+                    p.read_bytes.saturating_sub(read),
+                    p.written_bytes.saturating_sub(written),
+                );
+                (p.pid, moved)
+            })
+            .collect();
+        self.previous = Some(
+            all.iter()
+                .map(|p| ((p.pid, p.start_time), (p.read_bytes, p.written_bytes)))
+                .collect(),
+        );
+        let value = |key: &SortKey, p: &Process| {
+            key.value(p, moved.get(&p.pid).copied().unwrap_or((0, 0)))
+        };
+
+        let mut selected: Vec<&Process> = Vec::new();
+        for key in &self.config.sort_by {
+            // There is nothing to rank disk I/O by until the second run
+            if key.is_disk() && previous.is_none() {
+                continue;
+            }
+            let mut ranked = processes.clone();
+            ranked.sort_by(|a, b| value(key, b).total_cmp(&value(key, a)));
+            for process in ranked.into_iter().take(self.config.top_n) {
+                if !selected.iter().any(|p| p.pid == process.pid) {
+                    selected.push(process);
+                }
+            }
+        }
+
+        let users = read_users();
+        let mut samples = Vec::new();
+        for process in selected {
+            samples.extend(self.describe(process, &users));
+        }
+        samples
+    }
+}
+
+/// Maps uids to user names from `/etc/passwd`.
+fn read_users() -> HashMap<String, String> {
+    let passwd = match std::fs::read_to_string("/etc/passwd") {
+        Ok(passwd) => passwd,
+        Err(_) => return HashMap::new(),
+    };
+
+    passwd
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?;
+            Some((uid.to_string(), name.to_string()))
+        })
+        .collect()
+}
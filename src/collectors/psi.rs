@@ -0,0 +1,175 @@
+//! Pressure stall information from `/proc/pressure`.
+//!
+//! PSI needs Linux 4.20 or later built with `CONFIG_PSI`; without it the
+//! files are missing and nothing is reported.
+
+use crate::backend::Backend;
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+
+/// The resources the kernel tracks pressure for.
+const RESOURCES: &[&str] = &["cpu", "memory", "io"];
+
+/// The averaging windows on each line and the `window` label they become.
+const WINDOWS: &[(&str, &str)] = &[("avg10", "10s"), ("avg60", "60s"), ("avg300", "300s")];
+
+#[derive(Default)]
+pub struct PsiCollector;
+
+impl PsiCollector {
+    pub fn new() -> Self {
+        PsiCollector
+    }
+}
+
+impl Collector for PsiCollector {
+    fn name(&self) -> &'static str {
+        "psi"
+    }
+
+    fn collect(&mut self, _backend: &dyn Backend) -> Vec<Sample> {
+        let mut samples = Vec::new();
+
+        for resource in RESOURCES {
+            let text = match procfs::read_proc(&format!("pressure/{}", resource)) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            samples.extend(parse_pressure(resource, &text));
+        }
+
+        samples
+    }
+}
+
+/// Parses one `/proc/pressure/<resource>` file, whose lines look like
+/// "some avg10=0.12 avg60=0.08 avg300=0.02 total=123456". The `cpu` file of
+/// kernels before 5.13 has no "full" line.
+fn parse_pressure(resource: &str, text: &str) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for line in text.lines() {
+        // "some" is time at least one task stalled, "full" time all
+        // non-idle tasks stalled at once
+        let mut fields = line.split_whitespace();
+        let scope = match fields.next() {
+            Some(scope) => scope,
+            None => continue,
+        };
+
+        for field in fields {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key, value),
+                None => continue,
+            };
+            if key == "total" {
+                // Cumulative stall time in microseconds
+                if let Ok(micros) = value.parse::<u64>() {
+                    samples.push(
+                        Sample::counter(
+                            "pressure.stall_time",
+                            Unit::Seconds,
+                            micros as f64 / 1_000_000.0,
+                        )
+                        .with_label("resource", resource)
+                        .with_label("scope", scope),
+                    );
+                }
+            } else if let Some((_, window)) = WINDOWS.iter().find(|(k, _)| *k == key) {
+                if let Ok(percent) = value.parse::<f64>() {
+                    samples.push(
+                        Sample::gauge("pressure.avg", Unit::Percent, percent)
+                            .with_label("resource", resource)
+                            .with_label("scope", scope)
+                            .with_label("window", *window),
+                    );
+                }
+            }
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricKind;
+
+    /// The value of the sample named `name` with the given `scope` and, for
+    /// averages, `window`.
+    fn value(samples: &[Sample], name: &str, scope: &str, window: Option<&str>) -> Option<f64> {
+        samples
+            .iter()
+            .find(|s| {
+                s.name == name
+                    && s.labels.get("scope").map(String::as_str) == Some(scope)
+                    && s.labels.get("window").map(String::as_str) == window
+            })
+            .and_then(|s| s.scalar_value())
+    }
+
+    #[test]
+    fn parses_some_and_full_lines() {
+        let text = "some avg10=1.53 avg60=0.87 avg300=0.25 total=2750000\n\
+                    full avg10=0.40 avg60=0.10 avg300=0.00 total=500000\n";
+        let samples = parse_pressure("memory", text);
+        assert_eq!(samples.len(), 8);
+        assert!(samples.iter().all(|s| s.labels["resource"] == "memory"));
+
+        assert_eq!(
+            value(&samples, "pressure.avg", "some", Some("10s")),
+            Some(1.53)
+        );
+        assert_eq!(
+            value(&samples, "pressure.avg", "some", Some("60s")),
+            Some(0.87)
+        );
+        assert_eq!(
+            value(&samples, "pressure.avg", "some", Some("300s")),
+            Some(0.25)
+        );
+        assert_eq!(
+            value(&samples, "pressure.avg", "full", Some("10s")),
+            Some(0.40)
+        );
+        assert_eq!(
+            value(&samples, "pressure.stall_time", "some", None),
+            Some(2.75)
+        );
+        assert_eq!(
+            value(&samples, "pressure.stall_time", "full", None),
+            Some(0.5)
+        );
+
+        let total = samples
+            .iter()
+            .find(|s| s.name == "pressure.stall_time")
+            .unwrap();
+        assert_eq!(total.kind, MetricKind::Counter);
+        assert_eq!(total.unit, Unit::Seconds);
+    }
+
+    #[test]
+    fn parses_a_file_with_only_some() {
+        let samples = parse_pressure(
+            "cpu",
+            "some avg10=0.00 avg60=0.05 avg300=0.01 total=12345\n",
+        );
+        assert_eq!(samples.len(), 4);
+        assert!(samples.iter().all(|s| s.labels["scope"] == "some"));
+        assert_eq!(
+            value(&samples, "pressure.avg", "some", Some("60s")),
+            Some(0.05)
+        );
+        assert_eq!(
+            value(&samples, "pressure.stall_time", "some", None),
+            Some(0.012345)
+        );
+    }
+
+    #[test]
+    fn skips_what_it_does_not_know() {
+        let text = "\nsome avg10=x avg5=1.0 total=-1 junk\n";
+        assert!(parse_pressure("io", text).is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+//! The set of collectors an agent runs, and when each is next due.
+
+use super::{Collector, CollectorsConfig};
+use crate::backend::{Backend, Refresh};
+use crate::backoff::XorShift;
+use crate::metrics::{now_millis, Sample};
+use std::time::{Duration, Instant};
+
+struct Entry {
+    collector: Box<dyn Collector>,
+    interval: Duration,
+    /// Delay after each aligned boundary.
+    offset: Duration,
+    next_run: Instant,
+    /// `next_run` in milliseconds since the Unix epoch, once runs are
+    /// aligned.
+    boundary: Option<u64>,
+}
+
+/// Runs each registered collector at its own interval, refreshing only the
+/// parts of the backend the due collectors read.
+pub struct Registry {
+    entries: Vec<Entry>,
+    align: bool,
+    /// Delay after each aligned boundary, the same for every collector so
+    /// that those sharing an interval still run together.
+    offset: Duration,
+    /// The wall clock is read once, with the monotonic clock it is mapped
+    /// to. Collectors due at the same boundary are then due at the same
+    /// instant, and stepping the system clock cannot make them run twice
+    /// or not at all.
+    started: Instant,
+    started_millis: u64,
+}
+
+impl Registry {
+    /// With `align`, collectors run on multiples of their interval since the
+    /// Unix epoch, shifted by a random offset of up to `jitter` drawn once
+    /// for the agent; otherwise every interval counted from their first run.
+    pub fn new(align: bool, jitter: Duration) -> Self {
+        let offset = if align {
+            jitter.mul_f64(XorShift::from_entropy().next_f64())
+        } else {
+            Duration::ZERO
+        };
+        Registry {
+            entries: Vec::new(),
+            align,
+            offset,
+            started: Instant::now(),
+            started_millis: now_millis(),
+        }
+    }
+
+    /// Registers every collector in the build that `config` enables, in the
+    /// order their samples appear in a batch. Collectors without an interval
+    /// of their own run every `default_interval`.
+    pub fn from_config(
+        config: &CollectorsConfig,
+        default_interval: Duration,
+    ) -> Result<Self, String> {
+        for name in config
+            .enabled
+            .iter()
+            .chain(&config.disabled)
+            .chain(config.intervals.keys())
+        {
+            if !available().contains(&name.as_str()) {
+                return Err(format!(
+                    "unknown collector {:?}, this build has {}",
+                    name,
+                    available().join(", ")
+                ));
+            }
+        }
+        if let Some((name, _)) = config.intervals.iter().find(|(_, secs)| **secs == 0) {
+            return Err(format!("interval of {} must be at least one second", name));
+        }
+
+        let mut registry = Registry::new(config.align, Duration::from_millis(config.jitter_ms));
+        for collector in builtin(config)? {
+            if !config.is_enabled(collector.name()) {
+                continue;
+            }
+            let interval = match config.intervals.get(collector.name()) {
+                Some(secs) => Duration::from_secs(*secs),
+                None => collector.interval().unwrap_or(default_interval),
+            };
+            registry.register(collector, interval);
+        }
+        if registry.is_empty() {
+            return Err("no collector is enabled".to_string());
+        }
+        Ok(registry)
+    }
+
+    /// Adds a collector, due right away and then every `interval`.
+    pub fn register(&mut self, collector: Box<dyn Collector>, interval: Duration) {
+        self.entries.push(Entry {
+            collector,
+            interval,
+            offset: self.offset,
+            next_run: Instant::now(),
+            boundary: None,
+        });
+    }
+
+    /// Names of the registered collectors.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|e| e.collector.name()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Everything the registered collectors read from the backend, the
+    /// only parts of it worth ever refreshing.
+    pub fn needs(&self) -> Refresh {
+        self.entries
+            .iter()
+            .fold(Refresh::default(), |plan, e| plan.union(e.collector.needs()))
+    }
+
+    /// When the next collector is due, or `None` if none are registered.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.entries.iter().map(|e| e.next_run).min()
+    }
+
+    /// Refreshes what the due collectors read from `backend`, runs them and
+    /// schedules their next run.
+    pub fn collect_due(&mut self, backend: &mut dyn Backend) -> Vec<Sample> {
+        let now = Instant::now();
+        let wall = self.started_millis + (now - self.started).as_millis() as u64;
+        let plan = self
+            .entries
+            .iter()
+            .filter(|e| e.next_run <= now)
+            .fold(Refresh::default(), |plan, e| {
+                plan.union(e.collector.needs())
+            });
+        if !plan.is_empty() {
+            backend.refresh(plan);
+        }
+
+        let mut samples = Vec::new();
+        for entry in &mut self.entries {
+            if entry.next_run > now {
+                continue;
+            }
+            samples.extend(entry.collector.collect(backend));
+            if self.align {
+                let boundary = entry.next_boundary(wall);
+                entry.boundary = Some(boundary);
+                entry.next_run =
+                    self.started + Duration::from_millis(boundary - self.started_millis);
+            } else {
+                // Runs missed while the agent was busy are skipped, not caught up
+                entry.next_run += entry.interval;
+                if entry.next_run <= now {
+                    entry.next_run = now + entry.interval;
+                }
+            }
+        }
+        samples
+    }
+}
+
+impl Entry {
+    /// The boundary to run at after a run at `wall`, in milliseconds since
+    /// the Unix epoch. Runs missed while the agent was busy are skipped.
+    fn next_boundary(&self, wall: u64) -> u64 {
+        let interval = self.interval.as_millis().max(1) as u64;
+        let offset = self.offset.as_millis() as u64 % interval;
+
+        match self.boundary {
+            Some(previous) if previous + interval > wall => previous + interval,
+            // The first run, or one so late the next boundary has passed too.
+            // A boundary right after the run is skipped, as rates over a
+            // sliver of an interval are mostly noise.
+            _ => {
+                let next = wall + interval - (wall + interval - offset) % interval;
+                if next - wall < interval / 2 {
+                    next + interval
+                } else {
+                    next
+                }
+            }
+        }
+    }
+}
+
+/// Names of the collectors compiled into this build.
+pub fn available() -> Vec<&'static str> {
+    builtin(&CollectorsConfig::default())
+        .map(|collectors| collectors.iter().map(|c| c.name()).collect())
+        .unwrap_or_default()
+}
+
+/// One of every collector compiled in, configured from `config`.
+// Each push has its own feature, so the list cannot be a `vec![]`
+#[allow(unused_mut, clippy::vec_init_then_push)]
+fn builtin(config: &CollectorsConfig) -> Result<Vec<Box<dyn Collector>>, String> {
+    // Unused when every collector is compiled out
+    let _ = config;
+    let mut collectors: Vec<Box<dyn Collector>> = Vec::new();
+
+    #[cfg(feature = "memory")]
+    collectors.push(Box::new(super::memory::MemoryCollector::new()));
+    #[cfg(feature = "cpu")]
+    collectors.push(Box::new(super::cpu::CpuCollector::new()));
+    #[cfg(feature = "system")]
+    collectors.push(Box::new(super::system::SystemCollector::new()));
+    #[cfg(feature = "disk")]
+    collectors.push(Box::new(super::disk::DiskCollector::new(
+        config.disk.clone(),
+    )));
+    #[cfg(feature = "diskio")]
+    collectors.push(Box::new(super::diskio::DiskIoCollector::new(
+        config.diskio.clone(),
+    )));
+    #[cfg(feature = "network")]
+    collectors.push(Box::new(super::network::NetworkCollector::new(
+        config.network.clone(),
+    )));
+    #[cfg(feature = "sockets")]
+    collectors.push(Box::new(super::sockets::SocketCollector::new()));
+    #[cfg(feature = "psi")]
+    collectors.push(Box::new(super::psi::PsiCollector::new()));
+    #[cfg(feature = "cgroup")]
+    collectors.push(Box::new(super::cgroup::CgroupCollector::new(
+        config.cgroup.clone(),
+    )));
+    #[cfg(feature = "sensors")]
+    collectors.push(Box::new(super::sensors::SensorCollector::new()));
+    #[cfg(feature = "cpufreq")]
+    collectors.push(Box::new(super::cpufreq::CpuFreqCollector::new()));
+    #[cfg(feature = "inventory")]
+    collectors.push(Box::new(super::inventory::InventoryCollector::new()));
+    #[cfg(feature = "process")]
+    collectors.push(Box::new(super::process::ProcessCollector::new(
+        config.process.clone(),
+    )));
+    #[cfg(feature = "watch")]
+    collectors.push(Box::new(super::watch::WatchCollector::new(&config.watch)?));
+    #[cfg(feature = "agent")]
+    collectors.push(Box::new(super::agent::AgentCollector::new()));
+
+    Ok(collectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Idle;
+
+    impl Collector for Idle {
+        fn name(&self) -> &'static str {
+            "idle"
+        }
+
+        fn needs(&self) -> Refresh {
+            Refresh::default()
+        }
+
+        fn collect(&mut self, _: &dyn Backend) -> Vec<Sample> {
+            Vec::new()
+        }
+    }
+
+    fn entry(interval_ms: u64, offset_ms: u64, boundary: Option<u64>) -> Entry {
+        Entry {
+            collector: Box::new(Idle),
+            interval: Duration::from_millis(interval_ms),
+            offset: Duration::from_millis(offset_ms),
+            next_run: Instant::now(),
+            boundary,
+        }
+    }
+
+    /// A whole hour since the epoch, so on every boundary.
+    const T: u64 = 1_699_999_200_000;
+
+    #[test]
+    fn first_run_aligns_to_the_interval() {
+        assert_eq!(entry(10_000, 0, None).next_boundary(T + 3_000), T + 10_000);
+        assert_eq!(entry(60_000, 0, None).next_boundary(T + 1), T + 60_000);
+        // Already on a boundary: the next one is a whole interval away
+        assert_eq!(entry(10_000, 0, None).next_boundary(T), T + 10_000);
+    }
+
+    #[test]
+    fn first_run_skips_a_boundary_less_than_half_an_interval_away() {
+        assert_eq!(entry(10_000, 0, None).next_boundary(T + 5_000), T + 10_000);
+        assert_eq!(entry(10_000, 0, None).next_boundary(T + 5_001), T + 20_000);
+        assert_eq!(entry(10_000, 0, None).next_boundary(T + 9_999), T + 20_000);
+    }
+
+    #[test]
+    fn boundaries_are_shifted_by_the_offset() {
+        assert_eq!(entry(10_000, 1_500, None).next_boundary(T), T + 11_500);
+        assert_eq!(entry(10_000, 1_500, None).next_boundary(T + 4_000), T + 11_500);
+        // An offset beyond the interval wraps around
+        assert_eq!(entry(10_000, 12_000, None).next_boundary(T), T + 12_000);
+    }
+
+    #[test]
+    fn later_runs_follow_the_previous_boundary() {
+        // However late the run, as long as the next boundary is still ahead
+        let e = entry(10_000, 0, Some(T + 10_000));
+        assert_eq!(e.next_boundary(T + 10_000), T + 20_000);
+        assert_eq!(e.next_boundary(T + 10_020), T + 20_000);
+        assert_eq!(e.next_boundary(T + 19_999), T + 20_000);
+    }
+
+    #[test]
+    fn late_runs_skip_missed_boundaries() {
+        let e = entry(10_000, 0, Some(T + 10_000));
+        // T + 20s was missed; the next is far enough away
+        assert_eq!(e.next_boundary(T + 23_000), T + 30_000);
+        // T + 30s is too close to be worth a run
+        assert_eq!(e.next_boundary(T + 28_000), T + 40_000);
+        // Exactly on the missed boundary counts as missing it
+        assert_eq!(e.next_boundary(T + 20_000), T + 30_000);
+    }
+
+    #[test]
+    fn every_boundary_is_aligned() {
+        for interval in [1_000, 5_000, 60_000, 3_600_000] {
+            for offset in [0, 250, 1_999] {
+                let mut e = entry(interval, offset, None);
+                let mut wall = T + 123_457;
+                for _ in 0..5 {
+                    let next = e.next_boundary(wall);
+                    assert!(next > wall);
+                    assert_eq!((next - offset % interval) % interval, 0);
+                    e.boundary = Some(next);
+                    wall = next + 7;
+                }
+            }
+        }
+    }
+}
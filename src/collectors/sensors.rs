@@ -0,0 +1,226 @@
+//! Temperatures, their thresholds and fan speeds.
+//!
+//! On Linux the hwmon drivers in `/sys/class/hwmon` are read directly, since
+//! they also expose fans, and the thermal zones in `/sys/class/thermal` cover
+//! the SoC sensors of boards that have no hwmon driver. Elsewhere the
+//! components the backend finds are reported instead.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+use std::fs;
+use std::path::Path;
+
+pub struct SensorCollector {
+    /// Whether hwmon had any sensors at start-up. Only without them does
+    /// the backend need to look for components, which on Linux means reading
+    /// the same hwmon files a second time.
+    hwmon: bool,
+}
+
+impl Default for SensorCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SensorCollector {
+    pub fn new() -> Self {
+        SensorCollector {
+            hwmon: !hwmon().is_empty(),
+        }
+    }
+}
+
+impl Collector for SensorCollector {
+    fn name(&self) -> &'static str {
+        "sensors"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            components: !self.hwmon,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let mut samples = if self.hwmon {
+            hwmon()
+        } else {
+            components(backend)
+        };
+        samples.extend(thermal_zones());
+        samples
+    }
+}
+
+/// Temperatures from the backend, which has no fan speeds.
+fn components(backend: &dyn Backend) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for component in backend.components() {
+        let mut readings = vec![Sample::gauge(
+            "sensor.temperature",
+            Unit::Celsius,
+            component.temperature as f64,
+        )];
+        if let Some(critical) = component.critical {
+            readings.push(Sample::gauge(
+                "sensor.temperature.critical",
+                Unit::Celsius,
+                critical as f64,
+            ));
+        }
+
+        samples.extend(readings.into_iter().map(|sample| {
+            sample
+                .with_label("source", "sysinfo")
+                .with_label("sensor", component.label.clone())
+        }));
+    }
+    samples
+}
+
+/// Every `temp*` and `fan*` input of every hwmon chip.
+fn hwmon() -> Vec<Sample> {
+    let entries = match fs::read_dir(procfs::sys_path("class/hwmon")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut samples = Vec::new();
+    for entry in entries.flatten() {
+        let mut dir = entry.path();
+        // Drivers from before Linux 3.15 keep their attributes on the device
+        if !dir.join("name").exists() {
+            dir = dir.join("device");
+        }
+        let chip = match read_trimmed(&dir.join("name")) {
+            Some(chip) => chip,
+            None => continue,
+        };
+
+        let mut inputs: Vec<String> = match fs::read_dir(&dir) {
+            Ok(files) => files
+                .flatten()
+                .filter_map(|f| f.file_name().into_string().ok())
+                .filter_map(|f| f.strip_suffix("_input").map(str::to_string))
+                .collect(),
+            Err(_) => continue,
+        };
+        inputs.sort();
+
+        for input in inputs {
+            let attr = |suffix: &str| procfs::read_u64(&dir.join(format!("{}_{}", input, suffix)));
+            let sensor = read_trimmed(&dir.join(format!("{}_label", input)))
+                .unwrap_or_else(|| input.clone());
+
+            // Temperatures are in millidegrees Celsius, fans in RPM
+            let mut readings = Vec::new();
+            if input.starts_with("temp") {
+                let celsius =
+                    |suffix: &str| read_celsius(&dir.join(format!("{}_{}", input, suffix)));
+                if let Some(value) = celsius("input") {
+                    readings.push(Sample::gauge("sensor.temperature", Unit::Celsius, value));
+                }
+                if let Some(value) = celsius("max") {
+                    readings.push(Sample::gauge(
+                        "sensor.temperature.max",
+                        Unit::Celsius,
+                        value,
+                    ));
+                }
+                if let Some(value) = celsius("crit") {
+                    readings.push(Sample::gauge(
+                        "sensor.temperature.critical",
+                        Unit::Celsius,
+                        value,
+                    ));
+                }
+            } else if input.starts_with("fan") {
+                if let Some(rpm) = attr("input") {
+                    readings.push(Sample::gauge("sensor.fan.speed", Unit::Rpm, rpm as f64));
+                }
+                if let Some(rpm) = attr("min") {
+                    readings.push(Sample::gauge("sensor.fan.min", Unit::Rpm, rpm as f64));
+                }
+            }
+
+            samples.extend(readings.into_iter().map(|sample| {
+                sample
+                    .with_label("source", "hwmon")
+                    .with_label("chip", chip.clone())
+                    .with_label("sensor", sensor.clone())
+            }));
+        }
+    }
+    samples
+}
+
+/// The temperature of each thermal zone and its critical trip point.
+fn thermal_zones() -> Vec<Sample> {
+    let entries = match fs::read_dir(procfs::sys_path("class/thermal")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut samples = Vec::new();
+    for entry in entries.flatten() {
+        let zone = entry.file_name().to_string_lossy().into_owned();
+        if !zone.starts_with("thermal_zone") {
+            continue;
+        }
+        let dir = entry.path();
+        // Reading a disabled zone fails, so it is skipped
+        let celsius = match read_celsius(&dir.join("temp")) {
+            Some(celsius) => celsius,
+            None => continue,
+        };
+        let kind = read_trimmed(&dir.join("type")).unwrap_or_else(|| zone.clone());
+
+        let mut readings = vec![Sample::gauge("sensor.temperature", Unit::Celsius, celsius)];
+        let critical = (0..)
+            .map(|i| dir.join(format!("trip_point_{}_type", i)))
+            .take_while(|path| path.exists())
+            .enumerate()
+            .find(|(_, path)| read_trimmed(path).as_deref() == Some("critical"))
+            .and_then(|(i, _)| read_celsius(&dir.join(format!("trip_point_{}_temp", i))));
+        if let Some(critical) = critical {
+            readings.push(Sample::gauge(
+                "sensor.temperature.critical",
+                Unit::Celsius,
+                critical,
+            ));
+        }
+
+        samples.extend(readings.into_iter().map(|sample| {
+            sample
+                .with_label("source", "thermal")
+                .with_label("chip", kind.clone())
+                .with_label("sensor", zone.clone())
+        }));
+    }
+    samples
+}
+
+/// Reads a temperature in millidegrees, which can be below zero outdoors.
+fn read_celsius(path: &Path) -> Option<f64> {
+    let millis: i64 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    Some(millis as f64 / 1000.0)
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asks_for_components_only_without_hwmon() {
+        assert!(!SensorCollector { hwmon: true }.needs().components);
+        assert!(SensorCollector { hwmon: false }.needs().components);
+    }
+}
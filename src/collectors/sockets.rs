@@ -0,0 +1,144 @@
+//! TCP connection states and UDP socket counts from `/proc/net`.
+
+use crate::backend::Backend;
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use crate::procfs;
+
+/// TCP states as numbered in `include/net/tcp_states.h`, which is how
+/// `/proc/net/tcp` prints them (in hex).
+const TCP_STATES: &[(u8, &str)] = &[
+    (0x01, "established"),
+    (0x02, "syn_sent"),
+    (0x03, "syn_recv"),
+    (0x04, "fin_wait1"),
+    (0x05, "fin_wait2"),
+    (0x06, "time_wait"),
+    (0x07, "close"),
+    (0x08, "close_wait"),
+    (0x09, "last_ack"),
+    (0x0A, "listen"),
+    (0x0B, "closing"),
+    (0x0C, "new_syn_recv"),
+];
+
+/// The `/proc/net` tables to read and the address family each one covers.
+const FAMILIES: &[(&str, &str, &str)] = &[
+    ("ipv4", "net/tcp", "net/udp"),
+    ("ipv6", "net/tcp6", "net/udp6"),
+];
+
+#[derive(Default)]
+pub struct SocketCollector;
+
+impl SocketCollector {
+    pub fn new() -> Self {
+        SocketCollector
+    }
+}
+
+impl Collector for SocketCollector {
+    fn name(&self) -> &'static str {
+        "sockets"
+    }
+
+    fn collect(&mut self, _backend: &dyn Backend) -> Vec<Sample> {
+        let mut samples = Vec::new();
+
+        for (family, tcp, udp) in FAMILIES {
+            // A missing table means the family is disabled, not zero sockets
+            if let Ok(table) = procfs::read_proc(tcp) {
+                for ((_, name), count) in TCP_STATES.iter().zip(tcp_counts(&table)) {
+                    samples.push(
+                        Sample::gauge("sockets.tcp", Unit::None, count as f64)
+                            .with_label("family", *family)
+                            .with_label("state", *name),
+                    );
+                }
+            }
+
+            if let Ok(table) = procfs::read_proc(udp) {
+                samples.push(
+                    Sample::gauge(
+                        "sockets.udp",
+                        Unit::None,
+                        socket_states(&table).count() as f64,
+                    )
+                    .with_label("family", *family),
+                );
+            }
+        }
+
+        samples
+    }
+}
+
+/// How many sockets of a `/proc/net/tcp` table are in each of [`TCP_STATES`].
+fn tcp_counts(table: &str) -> [u64; TCP_STATES.len()] {
+    let mut counts = [0u64; TCP_STATES.len()];
+    for state in socket_states(table) {
+        if let Some(i) = TCP_STATES.iter().position(|(s, _)| *s == state) {
+            counts[i] += 1;
+        }
+    }
+    counts
+}
+
+/// The state column of every socket in a `/proc/net/{tcp,udp}` table.
+fn socket_states(table: &str) -> impl Iterator<Item = u8> + '_ {
+    table.lines().skip(1).filter_map(|line| {
+        let state = line.split_whitespace().nth(3)?;
+        u8::from_str_radix(state, 16).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP: &str = "\
+  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21781 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 33012 1 0000000000000000 100 0 0 10 0
+   2: 0F02000A:0016 0202000A:C4B2 01 00000000:00000000 02:0009C3E5 00000000     0        0 40213 4 0000000000000000 20 4 29 10 -1
+   3: 0F02000A:A1C4 2E0EB5AC:01BB 06 00000000:00000000 03:00001520 00000000     0        0 0 3 0000000000000000
+   4: 0F02000A:A1C6 2E0EB5AC:01BB 08 00000000:00000000 00:00000000 00000000  1000        0 40988 1 0000000000000000 20 4 30 10 -1
+";
+
+    const UDP6: &str = "\
+  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  131: 00000000000000000000000000000000:0222 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 19270 2 0000000000000000 0
+";
+
+    fn count(counts: &[u64], state: &str) -> u64 {
+        let i = TCP_STATES.iter().position(|(_, s)| *s == state).unwrap();
+        counts[i]
+    }
+
+    #[test]
+    fn reads_the_hex_state_column() {
+        let states: Vec<u8> = socket_states(TCP).collect();
+        assert_eq!(states, vec![0x0A, 0x0A, 0x01, 0x06, 0x08]);
+        assert_eq!(socket_states(UDP6).collect::<Vec<_>>(), vec![0x07]);
+    }
+
+    #[test]
+    fn counts_tcp_states() {
+        let counts = tcp_counts(TCP);
+        assert_eq!(count(&counts, "listen"), 2);
+        assert_eq!(count(&counts, "established"), 1);
+        assert_eq!(count(&counts, "time_wait"), 1);
+        assert_eq!(count(&counts, "close_wait"), 1);
+        assert_eq!(counts.iter().sum::<u64>(), 5);
+    }
+
+    #[test]
+    fn an_empty_table_has_no_sockets() {
+        let header = TCP.lines().next().unwrap();
+        assert_eq!(socket_states(header).count(), 0);
+        assert_eq!(socket_states("").count(), 0);
+        // Unknown states are skipped rather than miscounted
+        let unknown = tcp_counts("header\n 0: 0:0 0:0 FF 0\n");
+        assert_eq!(unknown.iter().sum::<u64>(), 0);
+    }
+}
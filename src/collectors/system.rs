@@ -0,0 +1,39 @@
+//! Load average, uptime and boot time.
+
+use crate::backend::{Backend, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+
+#[derive(Default)]
+pub struct SystemCollector;
+
+impl SystemCollector {
+    pub fn new() -> Self {
+        SystemCollector
+    }
+}
+
+impl Collector for SystemCollector {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            system: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let load = backend.load_average();
+
+        vec![
+            Sample::gauge("system.load", Unit::None, load.one).with_label("period", "1m"),
+            Sample::gauge("system.load", Unit::None, load.five).with_label("period", "5m"),
+            Sample::gauge("system.load", Unit::None, load.fifteen).with_label("period", "15m"),
+            Sample::gauge("system.uptime", Unit::Seconds, backend.uptime() as f64),
+            Sample::gauge("system.boot_time", Unit::Seconds, backend.boot_time() as f64),
+        ]
+    }
+}
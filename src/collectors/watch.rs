@@ -0,0 +1,318 @@
+//! Liveness of specific processes listed in the agent config.
+//!
+//! Each watch matches processes by exact name, by a regular expression over
+//! the command line, or by the pid in a pidfile, and reports whether any are
+//! running, how many, how long the oldest has been up and how often it was
+//! restarted: came back after a collection found none running, had its
+//! oldest process replaced by one started since the previous collection, or,
+//! for a pidfile, came back under a new pid.
+
+use crate::backend::{Backend, Process, Refresh};
+use crate::collectors::Collector;
+use crate::metrics::{Sample, Unit};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One `[[collectors.watch]]` entry. Exactly one of `process_name`, `regex`
+/// and `pidfile` must be set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchConfig {
+    /// Reported as the `watch` label.
+    pub name: String,
+    pub process_name: Option<String>,
+    /// Matched against the full command line.
+    pub regex: Option<String>,
+    pub pidfile: Option<PathBuf>,
+}
+
+enum Matcher {
+    Name(String),
+    Regex(Regex),
+    Pidfile(PathBuf),
+}
+
+impl Matcher {
+    /// The processes among `processes` this matcher selects.
+    fn find<'a>(&self, processes: &'a [Process]) -> Vec<&'a Process> {
+        match self {
+            Matcher::Name(name) => processes.iter().filter(|p| p.name == *name).collect(),
+            Matcher::Regex(regex) => processes
+                .iter()
+                .filter(|p| {
+                    let cmdline = p.cmd.join(" ");
+                    regex.is_match(if cmdline.is_empty() { &p.name } else { &cmdline })
+                })
+                .collect(),
+            Matcher::Pidfile(path) => {
+                let pid: u32 = match fs::read_to_string(path).map(|pid| pid.trim().parse()) {
+                    Ok(Ok(pid)) => pid,
+                    _ => return Vec::new(),
+                };
+                processes.iter().filter(|p| p.pid == pid).collect()
+            }
+        }
+    }
+}
+
+/// A compiled watch and what was seen in previous collections.
+struct Watch {
+    name: String,
+    matcher: Matcher,
+    /// Whether anything matched at the previous collection; `None` before
+    /// the first.
+    was_running: Option<bool>,
+    /// Pid and start time of the oldest match at the previous collection.
+    oldest: Option<(u32, u64)>,
+    /// When the previous collection ran, in seconds since the epoch.
+    last_seen: u64,
+    /// The pidfile's pid the last time it was running.
+    last_pid: Option<u32>,
+    restarts: u64,
+}
+
+impl Watch {
+    fn new(config: &WatchConfig) -> Result<Self, String> {
+        let matcher = match (&config.process_name, &config.regex, &config.pidfile) {
+            (Some(name), None, None) => Matcher::Name(name.clone()),
+            (None, Some(regex), None) => Matcher::Regex(
+                Regex::new(regex).map_err(|e| format!("watch {:?}: {}", config.name, e))?,
+            ),
+            (None, None, Some(path)) => Matcher::Pidfile(path.clone()),
+            _ => {
+                return Err(format!(
+                    "watch {:?} needs exactly one of process_name, regex or pidfile",
+                    config.name
+                ))
+            }
+        };
+
+        Ok(Watch {
+            name: config.name.clone(),
+            matcher,
+            was_running: None,
+            oldest: None,
+            last_seen: 0,
+            last_pid: None,
+            restarts: 0,
+        })
+    }
+
+    /// Counts a restart if `processes`, what matches at `now`, shows one.
+    fn observe(&mut self, processes: &[&Process], now: u64) {
+        let running = !processes.is_empty();
+        let mut restarted = self.was_running == Some(false) && running;
+
+        // Restarted between two collections, so never seen down. Worker
+        // processes come and go, and one that was already running may become
+        // the oldest, so only a replacement started since counts
+        let oldest = processes
+            .iter()
+            .min_by_key(|p| p.start_time)
+            .map(|p| (p.pid, p.start_time));
+        if let (Some((pid, start_time)), Some(new)) = (self.oldest, oldest) {
+            let gone = !processes
+                .iter()
+                .any(|p| p.pid == pid && p.start_time == start_time);
+            restarted |= gone && new.1 > self.last_seen;
+        }
+        self.oldest = oldest;
+        self.last_seen = now;
+
+        if let (Matcher::Pidfile(_), Some(process)) = (&self.matcher, processes.first()) {
+            // The pidfile names the one process, so any new pid is a restart
+            restarted |= matches!(self.last_pid, Some(last) if last != process.pid);
+            self.last_pid = Some(process.pid);
+        }
+        if restarted {
+            self.restarts += 1;
+        }
+        self.was_running = Some(running);
+    }
+}
+
+pub struct WatchCollector {
+    watches: Vec<Watch>,
+}
+
+impl WatchCollector {
+    /// Compiles every watch, failing on the first invalid one.
+    pub fn new(configs: &[WatchConfig]) -> Result<Self, String> {
+        let watches = configs.iter().map(Watch::new).collect::<Result<_, _>>()?;
+        Ok(WatchCollector { watches })
+    }
+}
+
+impl Collector for WatchCollector {
+    fn name(&self) -> &'static str {
+        "watch"
+    }
+
+    fn needs(&self) -> Refresh {
+        Refresh {
+            processes: true,
+            ..Refresh::default()
+        }
+    }
+
+    fn collect(&mut self, backend: &dyn Backend) -> Vec<Sample> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let all = backend.processes();
+        let mut samples = Vec::new();
+        for watch in &mut self.watches {
+            let processes = watch.matcher.find(&all);
+            let oldest = processes.iter().min_by_key(|p| p.start_time);
+            watch.observe(&processes, now);
+
+            let running = if processes.is_empty() { 0.0 } else { 1.0 };
+            let mut series = vec![
+                Sample::gauge("process.watch.running", Unit::None, running),
+                Sample::gauge(
+                    "process.watch.instances",
+                    Unit::None,
+                    processes.len() as f64,
+                ),
+                Sample::counter(
+                    "process.watch.restarts",
+                    Unit::None,
+                    watch.restarts as f64,
+                ),
+            ];
+            if let Some(oldest) = oldest {
+                series.push(Sample::gauge(
+                    "process.watch.uptime",
+                    Unit::Seconds,
+                    now.saturating_sub(oldest.start_time) as f64,
+                ));
+            }
+
+            samples.extend(
+                series
+                    .into_iter()
+                    .map(|sample| sample.with_label("watch", watch.name.clone())),
+            );
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(config: WatchConfig) -> Watch {
+        Watch::new(&config).unwrap()
+    }
+
+    fn by_name() -> Watch {
+        watch(WatchConfig {
+            name: "web".to_string(),
+            process_name: Some("nginx".to_string()),
+            regex: None,
+            pidfile: None,
+        })
+    }
+
+    fn by_pidfile() -> Watch {
+        watch(WatchConfig {
+            name: "sshd".to_string(),
+            process_name: None,
+            regex: None,
+            pidfile: Some(PathBuf::from("/run/sshd.pid")),
+        })
+    }
+
+    fn process(pid: u32) -> Process {
+        started(pid, 0)
+    }
+
+    fn started(pid: u32, start_time: u64) -> Process {
+        Process {
+            pid,
+            start_time,
+            ..Process::default()
+        }
+    }
+
+    #[test]
+    fn counts_a_comeback_as_a_restart() {
+        let (a, b) = (process(10), process(20));
+        let mut watch = by_name();
+        watch.observe(&[&a], 0);
+        watch.observe(&[], 0);
+        watch.observe(&[&b], 0);
+        assert_eq!(watch.restarts, 1);
+        watch.observe(&[], 0);
+        watch.observe(&[], 0);
+        watch.observe(&[&a, &b], 0);
+        assert_eq!(watch.restarts, 2);
+    }
+
+    #[test]
+    fn workers_coming_and_going_are_not_restarts() {
+        let (master, worker, other) = (process(10), process(11), process(12));
+        let mut watch = by_name();
+        watch.observe(&[&master, &worker], 0);
+        watch.observe(&[&worker], 0);
+        watch.observe(&[&worker, &other], 0);
+        assert_eq!(watch.restarts, 0);
+    }
+
+    #[test]
+    fn not_running_at_start_up_is_not_a_restart() {
+        let mut watch = by_name();
+        watch.observe(&[&process(10)], 0);
+        assert_eq!(watch.restarts, 0);
+
+        let mut watch = by_name();
+        watch.observe(&[], 0);
+        watch.observe(&[&process(10)], 0);
+        assert_eq!(watch.restarts, 1);
+    }
+
+    #[test]
+    fn a_new_pid_in_the_pidfile_is_a_restart() {
+        let (old, new) = (process(10), process(20));
+        let mut watch = by_pidfile();
+        watch.observe(&[&old], 0);
+        watch.observe(&[&old], 0);
+        assert_eq!(watch.restarts, 0);
+        // Restarted between two collections
+        watch.observe(&[&new], 0);
+        assert_eq!(watch.restarts, 1);
+        // Seen down, then back under yet another pid: one restart
+        watch.observe(&[], 0);
+        watch.observe(&[&old], 0);
+        assert_eq!(watch.restarts, 2);
+    }
+
+    #[test]
+    fn a_replaced_oldest_process_is_a_restart() {
+        let mut watch = by_name();
+        watch.observe(&[&started(10, 100), &started(11, 100)], 1000);
+        // Crashed and restarted by its supervisor between two collections
+        watch.observe(&[&started(20, 1003), &started(21, 1003)], 1005);
+        assert_eq!(watch.restarts, 1);
+        // And again, faster than collections run
+        watch.observe(&[&started(30, 1007)], 1010);
+        watch.observe(&[&started(40, 1012)], 1015);
+        assert_eq!(watch.restarts, 3);
+        watch.observe(&[&started(40, 1012)], 1020);
+        assert_eq!(watch.restarts, 3);
+    }
+
+    #[test]
+    fn a_worker_outliving_its_master_is_not_a_restart() {
+        let mut watch = by_name();
+        watch.observe(&[&started(10, 100), &started(11, 500)], 1000);
+        watch.observe(&[&started(11, 500), &started(12, 1002)], 1005);
+        assert_eq!(watch.restarts, 0);
+    }
+}
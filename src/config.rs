@@ -0,0 +1,585 @@
+//! Configuration for both binaries.
+//!
+//! Settings are layered, later sources winning over earlier ones:
+//!
+//! 1. built-in defaults,
+//! 2. a TOML file given with `--config` or `WASI_METRICS_CONFIG`,
+//! 3. `WASI_METRICS_*` environment variables,
+//! 4. command-line flags.
+//!
+//! Every override has the same name in the environment and on the command
+//! line: `--host-id edge-01` and `WASI_METRICS_HOST_ID=edge-01` are
+//! equivalent. List settings take comma-separated values, and repeating a
+//! flag appends to the list.
+
+use crate::backend::BackendKind;
+use crate::backoff::Backoff;
+use crate::collectors::{CollectorsConfig, Registry};
+use crate::sinks::SinksConfig;
+use crate::spool::{DropPolicy, SpoolConfig};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Prefix of every environment override.
+pub const ENV_PREFIX: &str = "WASI_METRICS_";
+
+/// Errors raised while loading configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A flag or environment variable that no setting answers to.
+    UnknownKey(String),
+    /// A value that does not fit its setting.
+    Invalid { key: String, reason: String },
+    /// A flag was given without its value.
+    MissingValue(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid {}: {}", path.display(), e),
+            ConfigError::UnknownKey(key) => write!(f, "unknown option --{}", key),
+            ConfigError::Invalid { key, reason } => write!(f, "invalid {}: {}", key, reason),
+            ConfigError::MissingValue(key) => write!(f, "option --{} needs a value", key),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            ConfigError::Parse(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A configuration that can be loaded from a file and then overridden
+/// setting by setting.
+pub trait Settings: Default + DeserializeOwned {
+    /// Names of the settings accepted by [`Settings::set`].
+    const KEYS: &'static [&'static str];
+
+    /// Applies one override from the environment or the command line.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError>;
+
+    /// Checks the final configuration once every layer has been applied.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+}
+
+/// What the command line asked for.
+pub enum Loaded<T> {
+    Config(T),
+    /// `--help` was given; the caller should print its usage and exit.
+    Help,
+}
+
+/// Loads `T` from its config file, the environment and `args` (without the
+/// program name).
+pub fn load<T: Settings>(args: impl IntoIterator<Item = String>) -> Result<Loaded<T>, ConfigError> {
+    load_from(args, |name| std::env::var(name).ok())
+}
+
+/// [`load`], with environment variables looked up through `env`.
+fn load_from<T: Settings>(
+    args: impl IntoIterator<Item = String>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Loaded<T>, ConfigError> {
+    let mut config_path = env(&format!("{}CONFIG", ENV_PREFIX)).map(PathBuf::from);
+    let mut flags: Vec<(String, String)> = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => {
+                return Err(ConfigError::Invalid {
+                    key: arg.clone(),
+                    reason: "expected an option starting with --".to_string(),
+                })
+            }
+        };
+        if flag == "help" {
+            return Ok(Loaded::Help);
+        }
+
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => match args.next() {
+                Some(value) => (flag.to_string(), value),
+                None => return Err(ConfigError::MissingValue(flag.to_string())),
+            },
+        };
+
+        if key == "config" {
+            config_path = Some(PathBuf::from(value));
+        } else if !T::KEYS.contains(&key.as_str()) {
+            return Err(ConfigError::UnknownKey(key));
+        } else if let Some(existing) = flags.iter_mut().find(|(k, _)| *k == key) {
+            // Repeated flags build up a list
+            existing.1.push(',');
+            existing.1.push_str(&value);
+        } else {
+            flags.push((key, value));
+        }
+    }
+
+    let mut config = match config_path {
+        Some(path) => {
+            let text = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(path, e))?
+        }
+        None => T::default(),
+    };
+
+    for key in T::KEYS {
+        if let Some(value) = env(&env_name(key)) {
+            config.set(key, &value)?;
+        }
+    }
+    for (key, value) in &flags {
+        config.set(key, value)?;
+    }
+
+    config.validate()?;
+    Ok(Loaded::Config(config))
+}
+
+/// The environment variable that overrides `key`, e.g. `WASI_METRICS_HOST_ID`.
+pub fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('-', "_").to_uppercase())
+}
+
+fn parse<V: FromStr>(key: &str, value: &str) -> Result<V, ConfigError>
+where
+    V::Err: fmt::Display,
+{
+    value.trim().parse().map_err(|e: V::Err| ConfigError::Invalid {
+        key: key.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Settings of the `metrics_client` agent.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub agent: AgentSection,
+    pub upstream: UpstreamSection,
+    pub spool: SpoolSection,
+    pub collectors: CollectorsConfig,
+    pub sinks: SinksConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentSection {
+    /// Identifies this agent to the server. Defaults to the host name.
+    pub host_id: Option<String>,
+    /// Seconds between two collections.
+    pub interval_secs: u64,
+    /// Where host-wide figures come from: `sysinfo`, or `procfs`, which is
+    /// the only choice on WASI and the default there.
+    pub backend: BackendKind,
+    /// Where the host's `/proc` and `/sys` are mounted, for runtimes that
+    /// preopen them under another path.
+    pub proc_root: PathBuf,
+    pub sys_root: PathBuf,
+}
+
+impl Default for AgentSection {
+    fn default() -> Self {
+        AgentSection {
+            host_id: None,
+            interval_secs: 5,
+            backend: BackendKind::default(),
+            proc_root: PathBuf::from("/proc"),
+            sys_root: PathBuf::from("/sys"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamSection {
+    /// Server addresses, tried in order until one accepts the connection.
+    pub servers: Vec<String>,
+    /// Seconds to wait when connecting and for each reply from the server.
+    pub timeout_secs: u64,
+    /// Delay before the first reconnect attempt, doubled after each failure.
+    pub backoff_initial_ms: u64,
+    /// Upper bound on the delay between reconnect attempts.
+    pub backoff_max_secs: u64,
+}
+
+impl Default for UpstreamSection {
+    fn default() -> Self {
+        UpstreamSection {
+            servers: vec!["127.0.0.1:8080".to_string()],
+            timeout_secs: 10,
+            backoff_initial_ms: 500,
+            backoff_max_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpoolSection {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+    pub segment_bytes: u64,
+    pub drop_policy: DropPolicy,
+}
+
+impl Default for SpoolSection {
+    fn default() -> Self {
+        let spool = SpoolConfig::default();
+        SpoolSection {
+            dir: spool.dir,
+            max_bytes: spool.max_bytes,
+            segment_bytes: spool.segment_bytes,
+            drop_policy: spool.drop_policy,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.agent.interval_secs)
+    }
+
+    pub fn upstream_timeout(&self) -> Duration {
+        Duration::from_secs(self.upstream.timeout_secs)
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        Backoff::new(
+            Duration::from_millis(self.upstream.backoff_initial_ms),
+            Duration::from_secs(self.upstream.backoff_max_secs),
+        )
+    }
+
+    pub fn spool_config(&self) -> SpoolConfig {
+        SpoolConfig {
+            dir: self.spool.dir.clone(),
+            max_bytes: self.spool.max_bytes,
+            segment_bytes: self.spool.segment_bytes,
+            drop_policy: self.spool.drop_policy,
+        }
+    }
+}
+
+impl Settings for ClientConfig {
+    const KEYS: &'static [&'static str] = &[
+        "host-id",
+        "interval",
+        "backend",
+        "proc-root",
+        "sys-root",
+        "server",
+        "timeout",
+        "backoff-initial-ms",
+        "backoff-max-secs",
+        "spool-dir",
+        "spool-max-bytes",
+        "spool-segment-bytes",
+        "drop-policy",
+        "collectors",
+        "disable-collectors",
+        "sinks",
+        "sink-file",
+        "sink-http",
+    ];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "host-id" => self.agent.host_id = Some(value.to_string()),
+            "interval" => self.agent.interval_secs = parse(key, value)?,
+            "backend" => self.agent.backend = parse(key, value)?,
+            "proc-root" => self.agent.proc_root = PathBuf::from(value),
+            "sys-root" => self.agent.sys_root = PathBuf::from(value),
+            "server" => self.upstream.servers = parse_list(value),
+            "timeout" => self.upstream.timeout_secs = parse(key, value)?,
+            "backoff-initial-ms" => self.upstream.backoff_initial_ms = parse(key, value)?,
+            "backoff-max-secs" => self.upstream.backoff_max_secs = parse(key, value)?,
+            "spool-dir" => self.spool.dir = PathBuf::from(value),
+            "spool-max-bytes" => self.spool.max_bytes = parse(key, value)?,
+            "spool-segment-bytes" => self.spool.segment_bytes = parse(key, value)?,
+            "drop-policy" => self.spool.drop_policy = parse(key, value)?,
+            "collectors" => self.collectors.enabled = parse_list(value),
+            "disable-collectors" => self.collectors.disabled = parse_list(value),
+            "sinks" => self.sinks.enabled = parse_list(value),
+            "sink-file" => self.sinks.file.path = PathBuf::from(value),
+            "sink-http" => self.sinks.http.url = value.to_string(),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.sinks.is_enabled("server") && self.upstream.servers.is_empty() {
+            return Err(ConfigError::Invalid {
+                key: "server".to_string(),
+                reason: "at least one server address is required".to_string(),
+            });
+        }
+        let spool_sizes = [
+            ("spool-max-bytes", self.spool.max_bytes),
+            ("spool-segment-bytes", self.spool.segment_bytes),
+        ];
+        if let Some((key, _)) = spool_sizes.iter().find(|(_, bytes)| *bytes == 0) {
+            return Err(ConfigError::Invalid {
+                key: key.to_string(),
+                reason: "must be at least one byte".to_string(),
+            });
+        }
+        if self.agent.interval_secs == 0 {
+            return Err(ConfigError::Invalid {
+                key: "interval".to_string(),
+                reason: "must be at least one second".to_string(),
+            });
+        }
+        if self.upstream.timeout_secs == 0 {
+            return Err(ConfigError::Invalid {
+                key: "timeout".to_string(),
+                reason: "must be at least one second".to_string(),
+            });
+        }
+        if !self.agent.backend.is_available() {
+            return Err(ConfigError::Invalid {
+                key: "backend".to_string(),
+                reason: format!("the {} backend is not part of this build", self.agent.backend),
+            });
+        }
+        if let Err(reason) = Registry::from_config(&self.collectors, self.interval()) {
+            return Err(ConfigError::Invalid {
+                key: "collectors".to_string(),
+                reason,
+            });
+        }
+        self.sinks.validate()
+    }
+}
+
+/// Settings of the `server`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to accept agent connections on.
+    pub listen: Vec<String>,
+    /// Agent connections served at once; further ones are told the server
+    /// is busy and closed.
+    pub max_connections: usize,
+    /// Seconds a connection may stay silent before it is closed. Must be
+    /// longer than the agents' collection interval.
+    pub read_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: vec!["127.0.0.1:8080".to_string()],
+            max_connections: 256,
+            read_timeout_secs: 120,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+}
+
+impl Settings for ServerConfig {
+    const KEYS: &'static [&'static str] = &["listen", "max-connections", "read-timeout"];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "listen" => self.listen = parse_list(value),
+            "max-connections" => self.max_connections = parse(key, value)?,
+            "read-timeout" => self.read_timeout_secs = parse(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.listen.is_empty() {
+            return Err(ConfigError::Invalid {
+                key: "listen".to_string(),
+                reason: "at least one listen address is required".to_string(),
+            });
+        }
+        if self.max_connections == 0 {
+            return Err(ConfigError::Invalid {
+                key: "max-connections".to_string(),
+                reason: "must be at least 1".to_string(),
+            });
+        }
+        if self.read_timeout_secs == 0 {
+            return Err(ConfigError::Invalid {
+                key: "read-timeout".to_string(),
+                reason: "must be at least one second".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn load_with<T: Settings>(cli: &[&str], env: &[(&str, &str)]) -> Result<T, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        match load_from(args(cli), |name| env.get(name).cloned())? {
+            Loaded::Config(config) => Ok(config),
+            Loaded::Help => panic!("unexpected --help"),
+        }
+    }
+
+    // The server's settings, unlike the agent's, are valid in any build
+    fn load_server(cli: &[&str], env: &[(&str, &str)]) -> Result<ServerConfig, ConfigError> {
+        load_with(cli, env)
+    }
+
+    fn load_client(cli: &[&str], env: &[(&str, &str)]) -> Result<ClientConfig, ConfigError> {
+        load_with(cli, env)
+    }
+
+    /// Writes a config file of its own for each test.
+    fn config_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "wasi_metrics-config-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let path = config_file(
+            "layers",
+            "listen = [\"file:1\"]\nmax_connections = 10\nread_timeout_secs = 30\n",
+        );
+        let path = path.to_str().unwrap();
+
+        let config = load_server(&[], &[]).unwrap();
+        assert_eq!(config.max_connections, 256);
+
+        let config = load_server(&["--config", path], &[]).unwrap();
+        assert_eq!(config.listen, vec!["file:1"]);
+        assert_eq!(config.max_connections, 10);
+        assert_eq!(config.read_timeout_secs, 30);
+
+        let env = [
+            ("WASI_METRICS_MAX_CONNECTIONS", "20"),
+            ("WASI_METRICS_READ_TIMEOUT", "40"),
+        ];
+        let config = load_server(&["--config", path], &env).unwrap();
+        assert_eq!(config.listen, vec!["file:1"]);
+        assert_eq!(config.max_connections, 20);
+        assert_eq!(config.read_timeout_secs, 40);
+
+        let config = load_server(&["--config", path, "--read-timeout", "50"], &env).unwrap();
+        assert_eq!(config.max_connections, 20);
+        assert_eq!(config.read_timeout_secs, 50);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn config_path_from_env_or_cli() {
+        let from_env = config_file("env-path", "max_connections = 1\n");
+        let from_cli = config_file("cli-path", "max_connections = 2\n");
+        let env = [("WASI_METRICS_CONFIG", from_env.to_str().unwrap())];
+
+        let config = load_server(&[], &env).unwrap();
+        assert_eq!(config.max_connections, 1);
+        let config = load_server(&["--config", from_cli.to_str().unwrap()], &env).unwrap();
+        assert_eq!(config.max_connections, 2);
+
+        fs::remove_file(from_env).unwrap();
+        fs::remove_file(from_cli).unwrap();
+    }
+
+    #[test]
+    fn repeated_flags_build_a_list() {
+        let config = load_server(&["--listen", "a:1", "--listen=b:2,c:3"], &[]).unwrap();
+        assert_eq!(config.listen, vec!["a:1", "b:2", "c:3"]);
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        assert!(matches!(
+            load_client(&["--no-such-flag", "1"], &[]),
+            Err(ConfigError::UnknownKey(key)) if key == "no-such-flag"
+        ));
+        assert!(matches!(
+            load_client(&["--interval"], &[]),
+            Err(ConfigError::MissingValue(key)) if key == "interval"
+        ));
+        assert!(matches!(
+            load_client(&["--interval", "soon"], &[]),
+            Err(ConfigError::Invalid { key, .. }) if key == "interval"
+        ));
+        assert!(matches!(
+            load_client(&[], &[("WASI_METRICS_INTERVAL", "0")]),
+            Err(ConfigError::Invalid { key, .. }) if key == "interval"
+        ));
+        assert!(matches!(
+            load_client(&["interval"], &[]),
+            Err(ConfigError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_file_keys() {
+        let path = config_file("unknown", "[agent]\nhostid = \"typo\"\n");
+        assert!(matches!(
+            load_client(&["--config", path.to_str().unwrap()], &[]),
+            Err(ConfigError::Parse(..))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn env_names() {
+        assert_eq!(env_name("host-id"), "WASI_METRICS_HOST_ID");
+        assert_eq!(env_name("spool-max-bytes"), "WASI_METRICS_SPOOL_MAX_BYTES");
+    }
+}
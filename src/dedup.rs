@@ -0,0 +1,93 @@
+//! Duplicate detection for batches retransmitted by agents.
+
+use crate::metrics::Batch;
+use std::collections::{HashMap, VecDeque};
+
+/// How many recent sessions are remembered per agent.
+///
+/// Batches from older sessions can still arrive when an agent replays what
+/// it buffered before a restart, so more than the current one is kept.
+const SESSIONS_PER_AGENT: usize = 8;
+
+/// Remembers the highest sequence number accepted for every agent session.
+///
+/// Agents send batches of a session in order and only move on once a batch
+/// has been acknowledged, so anything at or below the high-water mark has
+/// already been stored.
+#[derive(Debug, Default)]
+pub struct Deduplicator {
+    agents: HashMap<String, VecDeque<(u64, u64)>>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `batch` and returns `true` if it has not been seen before.
+    pub fn accept(&mut self, batch: &Batch) -> bool {
+        let sessions = self.agents.entry(batch.host_id.clone()).or_default();
+
+        // Sessions are kept least recently seen first
+        if let Some(i) = sessions.iter().position(|(s, _)| *s == batch.session) {
+            let (session, high_water) = sessions.remove(i).expect("position is in range");
+            let fresh = batch.seq > high_water;
+            sessions.push_back((session, high_water.max(batch.seq)));
+            return fresh;
+        }
+
+        sessions.push_back((batch.session, batch.seq));
+        if sessions.len() > SESSIONS_PER_AGENT {
+            // A session replaying its spool is still being seen, however
+            // old its id, so the one that went quiet longest is forgotten
+            sessions.pop_front();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(host_id: &str, session: u64, seq: u64) -> Batch {
+        Batch::new(host_id, session, seq)
+    }
+
+    #[test]
+    fn drops_batches_at_or_below_the_high_water_mark() {
+        let mut dedup = Deduplicator::new();
+        assert!(dedup.accept(&batch("a", 1, 1)));
+        assert!(dedup.accept(&batch("a", 1, 2)));
+        assert!(!dedup.accept(&batch("a", 1, 2)));
+        assert!(!dedup.accept(&batch("a", 1, 1)));
+        assert!(dedup.accept(&batch("a", 1, 5)));
+        assert!(!dedup.accept(&batch("a", 1, 3)));
+    }
+
+    #[test]
+    fn tracks_sessions_and_agents_apart() {
+        let mut dedup = Deduplicator::new();
+        assert!(dedup.accept(&batch("a", 1, 3)));
+        assert!(dedup.accept(&batch("a", 2, 1)));
+        assert!(dedup.accept(&batch("b", 1, 1)));
+        assert!(!dedup.accept(&batch("a", 1, 3)));
+        assert!(!dedup.accept(&batch("b", 1, 1)));
+    }
+
+    #[test]
+    fn forgets_the_least_recently_seen_session() {
+        let mut dedup = Deduplicator::new();
+        // Session 1 is the oldest id but keeps sending, as when an agent
+        // replays its spool after restarts
+        for session in 1..=SESSIONS_PER_AGENT as u64 {
+            assert!(dedup.accept(&batch("a", session, 1)));
+        }
+        assert!(dedup.accept(&batch("a", 1, 2)));
+        assert!(dedup.accept(&batch("a", 100, 1)));
+
+        assert!(!dedup.accept(&batch("a", 1, 2)), "session 1 was evicted");
+        // Session 2 went quiet longest, so it was forgotten
+        assert!(dedup.accept(&batch("a", 2, 1)));
+    }
+}
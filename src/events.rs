@@ -0,0 +1,205 @@
+//! Events the server derives by comparing consecutive batches from an agent.
+
+use crate::metrics::{now_millis, Batch};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// The host's uptime went backwards between two samples.
+    HostRebooted,
+    /// A watched process was running in one sample and not in the next.
+    ProcessDown,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::HostRebooted => "host rebooted",
+            EventKind::ProcessDown => "process down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub host_id: String,
+    pub kind: EventKind,
+    pub message: String,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {}: {}", self.kind.name(), self.host_id, self.message)
+    }
+}
+
+/// What was last seen from one host.
+#[derive(Debug, Default)]
+struct HostState {
+    uptime: Option<f64>,
+    /// Whether each watched process was running, by its `watch` label.
+    watches: HashMap<String, bool>,
+}
+
+/// Watches the batches of every agent and raises events on notable changes.
+#[derive(Debug, Default)]
+pub struct EventDetector {
+    hosts: HashMap<String, HostState>,
+}
+
+impl EventDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compares `batch` with what was previously seen from the same host.
+    pub fn inspect(&mut self, batch: &Batch) -> Vec<Event> {
+        let mut events = Vec::new();
+        let state = self.hosts.entry(batch.host_id.clone()).or_default();
+
+        let uptime = batch
+            .samples
+            .iter()
+            .find(|s| s.name == "system.uptime")
+            .and_then(|s| s.scalar_value());
+        if let Some(uptime) = uptime {
+            if let Some(previous) = state.uptime {
+                if uptime < previous {
+                    events.push(Event {
+                        host_id: batch.host_id.clone(),
+                        kind: EventKind::HostRebooted,
+                        message: format!("uptime went from {}s to {}s", previous, uptime),
+                        timestamp_ms: now_millis(),
+                    });
+                }
+            }
+            state.uptime = Some(uptime);
+        }
+
+        for sample in batch.samples.iter().filter(|s| s.name == "process.watch.running") {
+            let (watch, running) = match (sample.labels.get("watch"), sample.scalar_value()) {
+                (Some(watch), Some(running)) => (watch, running > 0.0),
+                _ => continue,
+            };
+            let was_running = state.watches.insert(watch.clone(), running);
+            if was_running == Some(true) && !running {
+                events.push(Event {
+                    host_id: batch.host_id.clone(),
+                    kind: EventKind::ProcessDown,
+                    message: format!("no process matches watch {:?}", watch),
+                    timestamp_ms: now_millis(),
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{Sample, Unit};
+
+    fn batch(host_id: &str, samples: Vec<Sample>) -> Batch {
+        let mut batch = Batch::new(host_id, 1, 1);
+        batch.samples = samples;
+        batch
+    }
+
+    fn uptime(host_id: &str, seconds: f64) -> Batch {
+        batch(
+            host_id,
+            vec![Sample::gauge("system.uptime", Unit::Seconds, seconds)],
+        )
+    }
+
+    fn kinds(events: &[Event]) -> Vec<EventKind> {
+        events.iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn uptime_going_backwards_is_a_reboot() {
+        let mut detector = EventDetector::new();
+        assert!(detector.inspect(&uptime("edge-01", 5000.0)).is_empty());
+
+        let events = detector.inspect(&uptime("edge-01", 30.0));
+        assert_eq!(kinds(&events), vec![EventKind::HostRebooted]);
+        assert_eq!(events[0].host_id, "edge-01");
+        // Compared with the rebooted uptime from now on
+        assert!(detector.inspect(&uptime("edge-01", 35.0)).is_empty());
+    }
+
+    #[test]
+    fn the_first_uptime_of_a_host_is_silent() {
+        let mut detector = EventDetector::new();
+        assert!(detector.inspect(&uptime("edge-01", 5000.0)).is_empty());
+        assert!(detector.inspect(&uptime("edge-02", 30.0)).is_empty());
+        // Batches without an uptime leave the last one in place
+        assert!(detector.inspect(&batch("edge-01", Vec::new())).is_empty());
+        assert_eq!(
+            kinds(&detector.inspect(&uptime("edge-01", 10.0))),
+            vec![EventKind::HostRebooted]
+        );
+    }
+
+    #[test]
+    fn steady_or_rising_uptime_is_silent() {
+        let mut detector = EventDetector::new();
+        for seconds in [100.0, 100.0, 105.0, 110.0] {
+            assert!(detector.inspect(&uptime("edge-01", seconds)).is_empty());
+        }
+    }
+
+    fn watch(host_id: &str, name: &str, running: bool) -> Batch {
+        let running = if running { 1.0 } else { 0.0 };
+        batch(
+            host_id,
+            vec![Sample::gauge("process.watch.running", Unit::None, running)
+                .with_label("watch", name)],
+        )
+    }
+
+    #[test]
+    fn a_watch_stopping_is_a_process_down() {
+        let mut detector = EventDetector::new();
+        assert!(detector.inspect(&watch("edge-01", "web", true)).is_empty());
+
+        let events = detector.inspect(&watch("edge-01", "web", false));
+        assert_eq!(kinds(&events), vec![EventKind::ProcessDown]);
+        assert!(events[0].message.contains("\"web\""));
+    }
+
+    #[test]
+    fn a_watch_down_from_the_start_is_silent() {
+        let mut detector = EventDetector::new();
+        assert!(detector.inspect(&watch("edge-01", "web", false)).is_empty());
+        assert!(detector.inspect(&watch("edge-01", "web", false)).is_empty());
+    }
+
+    #[test]
+    fn a_watch_staying_down_is_reported_once() {
+        let mut detector = EventDetector::new();
+        detector.inspect(&watch("edge-01", "web", true));
+        assert_eq!(detector.inspect(&watch("edge-01", "web", false)).len(), 1);
+        assert!(detector.inspect(&watch("edge-01", "web", false)).is_empty());
+        assert!(detector.inspect(&watch("edge-01", "web", false)).is_empty());
+
+        // Reported again after it came back and stopped once more
+        detector.inspect(&watch("edge-01", "web", true));
+        assert_eq!(detector.inspect(&watch("edge-01", "web", false)).len(), 1);
+    }
+
+    #[test]
+    fn watches_are_told_apart_by_label_and_host() {
+        let mut detector = EventDetector::new();
+        detector.inspect(&watch("edge-01", "web", true));
+        assert!(detector.inspect(&watch("edge-01", "db", false)).is_empty());
+        assert!(detector.inspect(&watch("edge-02", "web", false)).is_empty());
+    }
+}
@@ -0,0 +1,16 @@
+//! Shared types used by the `metrics_client` agent and the `server`.
+
+pub mod backend;
+pub mod backoff;
+pub mod collectors;
+pub mod config;
+pub mod dedup;
+pub mod events;
+pub mod metrics;
+pub mod procfs;
+pub mod protocol;
+pub mod sinks;
+pub mod spool;
+pub mod upstream;
+
+pub use metrics::{Batch, Labels, MetricKind, Sample, Unit, Value};
@@ -0,0 +1,208 @@
+//! The metrics data model exchanged between the agent and the server.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Labels attached to a sample, e.g. `cpu="3"` or `mount="/"`.
+///
+/// A `BTreeMap` keeps the labels sorted so the same series always renders
+/// and serializes identically.
+pub type Labels = BTreeMap<String, String>;
+
+/// How a metric's value should be interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    /// A value that can go up and down, like available memory.
+    Gauge,
+    /// A monotonically increasing total, like bytes received.
+    Counter,
+    /// A distribution of observations grouped into buckets.
+    Histogram,
+}
+
+/// The unit a metric's value is expressed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unit {
+    /// A dimensionless value or plain count.
+    None,
+    Bytes,
+    BytesPerSecond,
+    /// Events per second, e.g. I/O operations.
+    PerSecond,
+    Percent,
+    Seconds,
+    Milliseconds,
+    Megahertz,
+    Celsius,
+    /// Revolutions per minute, for fans.
+    Rpm,
+}
+
+impl Unit {
+    /// The suffix used when printing a value in this unit.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::None => "",
+            Unit::Bytes => "B",
+            Unit::BytesPerSecond => "B/s",
+            Unit::PerSecond => "/s",
+            Unit::Percent => "%",
+            Unit::Seconds => "s",
+            Unit::Milliseconds => "ms",
+            Unit::Megahertz => "MHz",
+            Unit::Celsius => "°C",
+            Unit::Rpm => "rpm",
+        }
+    }
+}
+
+/// One bucket of a histogram: the number of observations `<= upper_bound`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub upper_bound: f64,
+    pub count: u64,
+}
+
+/// A cumulative histogram of observations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub buckets: Vec<Bucket>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+/// The value carried by a sample.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Value {
+    /// A single number, used by gauges and counters.
+    Scalar(f64),
+    Histogram(Histogram),
+}
+
+/// A single observation of a metric at a point in time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub name: String,
+    pub kind: MetricKind,
+    pub unit: Unit,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: Labels,
+    pub value: Value,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+}
+
+impl Sample {
+    /// Creates a gauge sample stamped with the current time.
+    pub fn gauge(name: &str, unit: Unit, value: f64) -> Self {
+        Self::scalar(name, MetricKind::Gauge, unit, value)
+    }
+
+    /// Creates a counter sample stamped with the current time.
+    pub fn counter(name: &str, unit: Unit, value: f64) -> Self {
+        Self::scalar(name, MetricKind::Counter, unit, value)
+    }
+
+    /// Creates a histogram sample stamped with the current time.
+    pub fn histogram(name: &str, unit: Unit, histogram: Histogram) -> Self {
+        Sample {
+            name: name.to_string(),
+            kind: MetricKind::Histogram,
+            unit,
+            labels: Labels::new(),
+            value: Value::Histogram(histogram),
+            timestamp_ms: now_millis(),
+        }
+    }
+
+    fn scalar(name: &str, kind: MetricKind, unit: Unit, value: f64) -> Self {
+        Sample {
+            name: name.to_string(),
+            kind,
+            unit,
+            labels: Labels::new(),
+            value: Value::Scalar(value),
+            timestamp_ms: now_millis(),
+        }
+    }
+
+    /// Adds a label to the sample.
+    pub fn with_label(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.labels.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Returns the scalar value, or `None` for histograms.
+    pub fn scalar_value(&self) -> Option<f64> {
+        match self.value {
+            Value::Scalar(v) => Some(v),
+            Value::Histogram(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.labels.is_empty() {
+            let labels: Vec<String> = self
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, v))
+                .collect();
+            write!(f, "{{{}}}", labels.join(","))?;
+        }
+        match &self.value {
+            Value::Scalar(v) => write!(f, " {}{}", v, self.unit.symbol()),
+            Value::Histogram(h) => write!(
+                f,
+                " count={} sum={}{} buckets={}",
+                h.count,
+                h.sum,
+                self.unit.symbol(),
+                h.buckets.len()
+            ),
+        }
+    }
+}
+
+/// A group of samples collected from one host in one pass.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Batch {
+    /// Identifies the agent that produced the samples.
+    pub host_id: String,
+    /// Identifies one run of the agent. Sequence numbers are only
+    /// comparable within the same session.
+    pub session: u64,
+    /// Increases by one for every batch the agent produces in a session.
+    pub seq: u64,
+    pub samples: Vec<Sample>,
+}
+
+impl Batch {
+    pub fn new(host_id: &str, session: u64, seq: u64) -> Self {
+        Batch {
+            host_id: host_id.to_string(),
+            session,
+            seq,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        self.samples.push(sample);
+    }
+}
+
+/// The current time in milliseconds since the Unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,154 @@
+//! Helpers for reading the Linux `/proc` and `/sys` pseudo filesystems.
+//!
+//! Collectors that need more than sysinfo exposes read these files directly.
+//! Missing files are normal on other platforms and older kernels, so callers
+//! treat a read error as "not available" rather than a failure.
+//!
+//! Both trees are normally at `/proc` and `/sys`, but can be mounted
+//! elsewhere, e.g. when a container or WASI runtime maps the host's under
+//! another path; [`set_roots`] points every reader at them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const PROC_ROOT: &str = "/proc";
+const SYS_ROOT: &str = "/sys";
+
+static ROOTS: OnceLock<(PathBuf, PathBuf)> = OnceLock::new();
+
+/// Reads `/proc` and `/sys` from `proc` and `sys` instead. Only the first
+/// call has an effect, and it must come before anything is read.
+pub fn set_roots(proc: PathBuf, sys: PathBuf) {
+    let _ = ROOTS.set((proc, sys));
+}
+
+/// Path of `rel` under `/proc`, e.g. `proc_path("stat")`.
+pub fn proc_path(rel: &str) -> PathBuf {
+    match ROOTS.get() {
+        Some((proc, _)) => proc.join(rel),
+        None => Path::new(PROC_ROOT).join(rel),
+    }
+}
+
+/// Path of `rel` under `/sys`, e.g. `sys_path("class/hwmon")`.
+pub fn sys_path(rel: &str) -> PathBuf {
+    match ROOTS.get() {
+        Some((_, sys)) => sys.join(rel),
+        None => Path::new(SYS_ROOT).join(rel),
+    }
+}
+
+/// Reads `/proc/<rel>` into a string.
+pub fn read_proc(rel: &str) -> io::Result<String> {
+    fs::read_to_string(proc_path(rel))
+}
+
+/// Reads `/sys/<rel>` into a string.
+pub fn read_sys(rel: &str) -> io::Result<String> {
+    fs::read_to_string(sys_path(rel))
+}
+
+/// Reads a file holding a single number, as most `/sys` attributes do.
+pub fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The `cpuN` directories of `/sys/devices/system/cpu`, by N.
+pub fn cpu_dirs() -> BTreeMap<usize, PathBuf> {
+    let entries = match fs::read_dir(sys_path("devices/system/cpu")) {
+        Ok(entries) => entries,
+        Err(_) => return BTreeMap::new(),
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let n = name.strip_prefix("cpu")?.parse().ok()?;
+            Some((n, entry.path()))
+        })
+        .collect()
+}
+
+/// Reads a CPU topology id, which is -1 on some platforms when unknown.
+pub fn read_topology_id(path: &Path) -> Option<u64> {
+    let id: i64 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    Some(id.max(0) as u64)
+}
+
+/// One interface's counters in `/proc/net/dev`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetDev {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_drops: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_drops: u64,
+}
+
+/// Parses `/proc/net/dev`, skipping its two header lines.
+pub fn parse_net_dev(text: &str) -> Vec<NetDev> {
+    text.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(':')?;
+            let fields: Vec<u64> = rest
+                .split_whitespace()
+                .map(|f| f.parse().unwrap_or(0))
+                .collect();
+            if fields.len() < 12 {
+                return None;
+            }
+            Some(NetDev {
+                name: name.trim().to_string(),
+                rx_bytes: fields[0],
+                rx_packets: fields[1],
+                rx_errors: fields[2],
+                rx_drops: fields[3],
+                tx_bytes: fields[8],
+                tx_packets: fields[9],
+                tx_errors: fields[10],
+                tx_drops: fields[11],
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn net_dev() {
+        let text = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  123456     100    0    0    0     0          0         0   123456     100    0    0    0     0       0          0
+  eth0:98765432   54321    2    7    0     0          0        11  1234567   4321    1    3    0     0       0          0
+ broken: 1 2 3
+";
+        let devs = parse_net_dev(text);
+        assert_eq!(devs.len(), 2);
+        assert_eq!(devs[0].name, "lo");
+        assert_eq!(
+            devs[1],
+            NetDev {
+                name: "eth0".to_string(),
+                rx_bytes: 98765432,
+                rx_packets: 54321,
+                rx_errors: 2,
+                rx_drops: 7,
+                tx_bytes: 1234567,
+                tx_packets: 4321,
+                tx_errors: 1,
+                tx_drops: 3,
+            }
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! The framed wire protocol spoken between the agent and the server.
+//!
+//! Every message is sent as one frame:
+//!
+//! ```text
+//! +-------+---------+------+----------------+-------------------+
+//! | magic | version | type | length (u32 BE) | payload (length) |
+//! | "WM"  |   u8    |  u8  |                 |   JSON document  |
+//! +-------+---------+------+----------------+-------------------+
+//! ```
+//!
+//! The header layout is fixed across protocol versions so a peer can always
+//! read a frame far enough to skip it and answer with the versions it
+//! supports.
+
+use crate::metrics::Batch;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// The protocol version this build speaks by default.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Every protocol version this build understands, oldest first.
+pub const SUPPORTED_VERSIONS: &[u8] = &[1];
+
+/// Marks the start of every frame.
+pub const MAGIC: [u8; 2] = *b"WM";
+
+/// Size of the fixed frame header in bytes.
+pub const HEADER_LEN: usize = 8;
+
+/// Frames larger than this are rejected instead of being buffered.
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// The first message an agent sends after connecting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    pub host_id: String,
+    /// Protocol versions the agent can speak.
+    pub versions: Vec<u8>,
+}
+
+/// Sent by the server to accept a `Hello`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Welcome {
+    /// The version both sides will use for the rest of the connection.
+    pub version: u8,
+}
+
+/// Sent by the server when it cannot talk to the peer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unsupported {
+    /// Protocol versions the server supports.
+    pub supported: Vec<u8>,
+    pub reason: String,
+}
+
+/// Sent by the server, instead of a `Welcome`, to an agent it has no room
+/// for. Unlike `Unsupported` it is temporary: the agent backs off and tries
+/// again, or tries another server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Busy {
+    pub reason: String,
+}
+
+/// Sent by the server once a batch has been stored, or recognised as a
+/// duplicate of one that was.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ack {
+    pub session: u64,
+    pub seq: u64,
+}
+
+/// A decoded protocol message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hello(Hello),
+    Welcome(Welcome),
+    Unsupported(Unsupported),
+    Batch(Batch),
+    Ack(Ack),
+    Busy(Busy),
+}
+
+impl Message {
+    /// The type byte written in the frame header.
+    pub fn type_id(&self) -> u8 {
+        match self {
+            Message::Hello(_) => 1,
+            Message::Welcome(_) => 2,
+            Message::Unsupported(_) => 3,
+            Message::Batch(_) => 4,
+            Message::Ack(_) => 5,
+            Message::Busy(_) => 6,
+        }
+    }
+
+    fn encode_payload(&self) -> Result<Vec<u8>, ProtocolError> {
+        let payload = match self {
+            Message::Hello(m) => serde_json::to_vec(m),
+            Message::Welcome(m) => serde_json::to_vec(m),
+            Message::Unsupported(m) => serde_json::to_vec(m),
+            Message::Batch(m) => serde_json::to_vec(m),
+            Message::Ack(m) => serde_json::to_vec(m),
+            Message::Busy(m) => serde_json::to_vec(m),
+        };
+        Ok(payload?)
+    }
+
+    fn decode_payload(type_id: u8, payload: &[u8]) -> Result<Message, ProtocolError> {
+        let message = match type_id {
+            1 => Message::Hello(serde_json::from_slice(payload)?),
+            2 => Message::Welcome(serde_json::from_slice(payload)?),
+            3 => Message::Unsupported(serde_json::from_slice(payload)?),
+            4 => Message::Batch(serde_json::from_slice(payload)?),
+            5 => Message::Ack(serde_json::from_slice(payload)?),
+            6 => Message::Busy(serde_json::from_slice(payload)?),
+            other => return Err(ProtocolError::UnknownType(other)),
+        };
+        Ok(message)
+    }
+}
+
+/// Errors raised while reading or writing frames.
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    /// The frame did not start with [`MAGIC`].
+    BadMagic([u8; 2]),
+    /// The frame used a protocol version this build does not speak. The
+    /// payload has already been skipped, so the stream is still in sync.
+    UnsupportedVersion(u8),
+    /// The frame used a message type this version does not define.
+    UnknownType(u8),
+    /// The frame announced a payload larger than [`MAX_FRAME_LEN`].
+    TooLarge(u32),
+    Decode(serde_json::Error),
+    /// The peer sent a valid message that makes no sense at this point.
+    Unexpected(u8),
+    /// The server refused the connection.
+    Rejected(Unsupported),
+    /// The server has no room for the connection right now.
+    Busy(Busy),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "I/O error: {}", e),
+            ProtocolError::BadMagic(m) => write!(f, "bad frame magic {:02x?}", m),
+            ProtocolError::UnsupportedVersion(v) => {
+                write!(f, "unsupported protocol version {}", v)
+            }
+            ProtocolError::UnknownType(t) => write!(f, "unknown message type {}", t),
+            ProtocolError::TooLarge(len) => write!(
+                f,
+                "frame of {} bytes exceeds the {} byte limit",
+                len, MAX_FRAME_LEN
+            ),
+            ProtocolError::Decode(e) => write!(f, "invalid payload: {}", e),
+            ProtocolError::Unexpected(t) => write!(f, "unexpected message type {}", t),
+            ProtocolError::Rejected(u) => write!(
+                f,
+                "rejected by server: {} (it supports protocol versions {:?})",
+                u.reason, u.supported
+            ),
+            ProtocolError::Busy(b) => write!(f, "server busy: {}", b.reason),
+        }
+    }
+}
+
+impl Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProtocolError::Io(e) => Some(e),
+            ProtocolError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> Self {
+        ProtocolError::Decode(e)
+    }
+}
+
+/// Returns whether this build can speak `version`.
+pub fn is_supported(version: u8) -> bool {
+    SUPPORTED_VERSIONS.contains(&version)
+}
+
+/// Picks the highest version both this build and the peer support.
+pub fn negotiate(peer_versions: &[u8]) -> Option<u8> {
+    SUPPORTED_VERSIONS
+        .iter()
+        .rev()
+        .find(|v| peer_versions.contains(v))
+        .copied()
+}
+
+/// Encodes `message` into a complete frame.
+pub fn encode_frame(version: u8, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    let payload = message.encode_payload()?;
+    if payload.len() > MAX_FRAME_LEN as usize {
+        return Err(ProtocolError::TooLarge(payload.len() as u32));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&MAGIC);
+    frame.push(version);
+    frame.push(message.type_id());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Writes `message` as a single frame.
+pub fn write_message<W: Write>(
+    writer: &mut W,
+    version: u8,
+    message: &Message,
+) -> Result<(), ProtocolError> {
+    let frame = encode_frame(version, message)?;
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads one whole frame, reassembling it from as many reads as it takes.
+///
+/// Returns `Ok(None)` when the peer closed the connection cleanly between
+/// frames.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Option<Message>, ProtocolError> {
+    let mut header = [0u8; HEADER_LEN];
+    if !read_header(reader, &mut header)? {
+        return Ok(None);
+    }
+
+    let magic = [header[0], header[1]];
+    if magic != MAGIC {
+        return Err(ProtocolError::BadMagic(magic));
+    }
+    let version = header[2];
+    let type_id = header[3];
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    if len > MAX_FRAME_LEN {
+        return Err(ProtocolError::TooLarge(len));
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+
+    if !is_supported(version) {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    Message::decode_payload(type_id, &payload).map(Some)
+}
+
+/// Fills `header`, returning `false` if the stream ended before any byte.
+fn read_header<R: Read>(reader: &mut R, header: &mut [u8]) -> Result<bool, ProtocolError> {
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{Sample, Unit};
+
+    fn batch() -> Message {
+        let mut batch = Batch::new("edge-01", 42, 7);
+        batch
+            .samples
+            .push(Sample::gauge("memory.total", Unit::Bytes, 1024.0).with_label("host", "a"));
+        Message::Batch(batch)
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let messages = [
+            Message::Hello(Hello {
+                host_id: "edge-01".to_string(),
+                versions: vec![1],
+            }),
+            Message::Welcome(Welcome { version: 1 }),
+            Message::Unsupported(Unsupported {
+                supported: vec![1],
+                reason: "version 2 is not supported".to_string(),
+            }),
+            batch(),
+            Message::Ack(Ack { session: 42, seq: 7 }),
+            Message::Busy(Busy {
+                reason: "already serving 256 connections".to_string(),
+            }),
+        ];
+        let mut stream = Vec::new();
+        for message in &messages {
+            write_message(&mut stream, PROTOCOL_VERSION, message).unwrap();
+        }
+
+        let mut reader = &stream[..];
+        for message in &messages {
+            assert_eq!(read_message(&mut reader).unwrap().as_ref(), Some(message));
+        }
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn header_layout() {
+        let frame = encode_frame(1, &Message::Welcome(Welcome { version: 1 })).unwrap();
+        assert_eq!(&frame[..2], b"WM");
+        assert_eq!(frame[2], 1);
+        assert_eq!(frame[3], 2);
+        let len = u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]) as usize;
+        assert_eq!(len, frame.len() - HEADER_LEN);
+    }
+
+    /// A reader that hands out one byte per call.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((byte, rest)), Some(slot)) => {
+                    *slot = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn reassembles_split_frames() {
+        let frame = encode_frame(1, &batch()).unwrap();
+        let message = read_message(&mut Trickle(&frame)).unwrap();
+        assert_eq!(message, Some(batch()));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut frame = encode_frame(1, &batch()).unwrap();
+        frame[0] = b'X';
+        match read_message(&mut &frame[..]) {
+            Err(ProtocolError::BadMagic(magic)) => assert_eq!(&magic, b"XM"),
+            other => panic!("expected bad magic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn skips_frames_of_unsupported_versions() {
+        let mut stream = encode_frame(9, &batch()).unwrap();
+        stream.extend(encode_frame(1, &Message::Ack(Ack { session: 1, seq: 2 })).unwrap());
+
+        let mut reader = &stream[..];
+        match read_message(&mut reader) {
+            Err(ProtocolError::UnsupportedVersion(9)) => {}
+            other => panic!("expected an unsupported version, got {:?}", other),
+        }
+        // The payload was consumed, so the next frame still parses
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(Message::Ack(Ack { session: 1, seq: 2 }))
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        let mut frame = encode_frame(1, &batch()).unwrap();
+        frame[4..8].copy_from_slice(&(MAX_FRAME_LEN + 1).to_be_bytes());
+        match read_message(&mut &frame[..]) {
+            Err(ProtocolError::TooLarge(len)) => assert_eq!(len, MAX_FRAME_LEN + 1),
+            other => panic!("expected a too large frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let frame = encode_frame(1, &batch()).unwrap();
+        for cut in [3, HEADER_LEN, frame.len() - 1] {
+            match read_message(&mut &frame[..cut]) {
+                Err(ProtocolError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+                other => panic!("expected an early end at {}, got {:?}", cut, other),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_types() {
+        let mut frame = encode_frame(1, &batch()).unwrap();
+        frame[3] = 99;
+        assert!(matches!(
+            read_message(&mut &frame[..]),
+            Err(ProtocolError::UnknownType(99))
+        ));
+    }
+
+    #[test]
+    fn negotiates_the_highest_common_version() {
+        assert_eq!(negotiate(&[1, 2, 3]), Some(1));
+        assert_eq!(negotiate(&[2]), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Batching and retries shared by the sinks that write somewhere simple.
+
+use super::Sink;
+use crate::backoff::Backoff;
+use crate::metrics::Batch;
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Somewhere a [`Buffered`] sink writes its batches.
+pub trait Output {
+    /// Writes `batches` in full or fails; a failed write is retried with the
+    /// same batches.
+    fn write(&mut self, batches: &[Batch]) -> io::Result<()>;
+}
+
+/// How a [`Buffered`] sink groups and retries its writes.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    /// Batches written together.
+    pub batch_size: usize,
+    /// Longest a batch waits for others to fill a write.
+    pub linger: Duration,
+    /// Batches kept while the output fails; the oldest are dropped first.
+    pub max_queued: usize,
+    pub backoff: Backoff,
+}
+
+/// A sink that queues batches in memory and writes them to an [`Output`].
+pub struct Buffered<O> {
+    name: &'static str,
+    output: O,
+    delivery: Delivery,
+    /// Batches waiting to be written, with when each was queued.
+    queue: VecDeque<(Instant, Batch)>,
+    next_attempt: Instant,
+    dropped: u64,
+}
+
+impl<O: Output> Buffered<O> {
+    pub fn new(name: &'static str, output: O, delivery: Delivery) -> Self {
+        Buffered {
+            name,
+            output,
+            delivery,
+            queue: VecDeque::new(),
+            next_attempt: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    /// Batches dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// When the queue is worth writing: once it fills a write, or once its
+    /// oldest batch has waited long enough.
+    fn ready_at(&self) -> Option<Instant> {
+        let (queued, _) = self.queue.front()?;
+        if self.queue.len() >= self.delivery.batch_size {
+            Some(*queued)
+        } else {
+            Some(*queued + self.delivery.linger)
+        }
+    }
+}
+
+impl<O: Output> Sink for Buffered<O> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn send(&mut self, batch: &Batch) {
+        self.queue.push_back((Instant::now(), batch.clone()));
+        if self.queue.len() > self.delivery.max_queued {
+            self.queue.pop_front();
+            self.dropped += 1;
+            eprintln!(
+                "The {} sink is full, dropped {} batches so far",
+                self.name, self.dropped
+            );
+        }
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        match self.ready_at() {
+            Some(ready) if ready <= now && self.next_attempt <= now => {}
+            _ => return,
+        }
+
+        while !self.queue.is_empty() {
+            let count = self.queue.len().min(self.delivery.batch_size);
+            let batches: Vec<Batch> = self
+                .queue
+                .iter()
+                .take(count)
+                .map(|(_, b)| b.clone())
+                .collect();
+            if let Err(e) = self.output.write(&batches) {
+                let delay = self.delivery.backoff.next_delay();
+                self.next_attempt = Instant::now() + delay;
+                eprintln!(
+                    "The {} sink failed ({}), retrying in {:.1}s",
+                    self.name,
+                    e,
+                    delay.as_secs_f64()
+                );
+                return;
+            }
+            self.queue.drain(..count);
+            self.delivery.backoff.reset();
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.ready_at().map(|ready| ready.max(self.next_attempt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+
+    /// Records every write, failing while `failing` is set.
+    #[derive(Clone, Default)]
+    struct Script {
+        writes: Rc<RefCell<Vec<Vec<u64>>>>,
+        attempts: Rc<RefCell<u32>>,
+        failing: Rc<RefCell<bool>>,
+    }
+
+    impl Output for Script {
+        fn write(&mut self, batches: &[Batch]) -> io::Result<()> {
+            *self.attempts.borrow_mut() += 1;
+            if *self.failing.borrow() {
+                return Err(io::Error::other("unavailable"));
+            }
+            self.writes
+                .borrow_mut()
+                .push(batches.iter().map(|b| b.seq).collect());
+            Ok(())
+        }
+    }
+
+    fn sink(batch_size: usize, linger: Duration, max_queued: usize) -> (Buffered<Script>, Script) {
+        let script = Script::default();
+        let delivery = Delivery {
+            batch_size,
+            linger,
+            max_queued,
+            backoff: Backoff::new(Duration::from_millis(40), Duration::from_secs(1)),
+        };
+        (Buffered::new("test", script.clone(), delivery), script)
+    }
+
+    fn batch(seq: u64) -> Batch {
+        Batch::new("edge-01", 1, seq)
+    }
+
+    #[test]
+    fn writes_once_a_batch_is_full() {
+        let (mut sink, script) = sink(3, Duration::from_secs(60), 100);
+        assert_eq!(sink.next_due(), None);
+
+        sink.send(&batch(1));
+        sink.send(&batch(2));
+        sink.flush();
+        assert!(script.writes.borrow().is_empty());
+        assert!(sink.next_due().unwrap() > Instant::now() + Duration::from_secs(50));
+
+        sink.send(&batch(3));
+        assert!(sink.next_due().unwrap() <= Instant::now());
+        sink.flush();
+        assert_eq!(*script.writes.borrow(), vec![vec![1, 2, 3]]);
+        assert_eq!(sink.next_due(), None);
+    }
+
+    #[test]
+    fn writes_a_partial_batch_once_it_lingered() {
+        let (mut sink, script) = sink(10, Duration::from_millis(30), 100);
+        sink.send(&batch(1));
+        sink.flush();
+        assert!(script.writes.borrow().is_empty());
+
+        thread::sleep(Duration::from_millis(40));
+        sink.flush();
+        assert_eq!(*script.writes.borrow(), vec![vec![1]]);
+    }
+
+    #[test]
+    fn splits_a_long_queue_into_writes_of_batch_size() {
+        let (mut sink, script) = sink(2, Duration::ZERO, 100);
+        for seq in 1..=5 {
+            sink.queue.push_back((Instant::now(), batch(seq)));
+        }
+        sink.flush();
+        assert_eq!(
+            *script.writes.borrow(),
+            vec![vec![1, 2], vec![3, 4], vec![5]]
+        );
+    }
+
+    #[test]
+    fn drops_the_oldest_once_full() {
+        let (mut sink, script) = sink(10, Duration::from_secs(60), 3);
+        for seq in 1..=5 {
+            sink.send(&batch(seq));
+        }
+        assert_eq!(sink.dropped(), 2);
+        let queued: Vec<u64> = sink.queue.iter().map(|(_, b)| b.seq).collect();
+        assert_eq!(queued, vec![3, 4, 5]);
+        assert!(script.writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn backs_off_after_a_failed_write() {
+        let (mut sink, script) = sink(1, Duration::ZERO, 100);
+        *script.failing.borrow_mut() = true;
+        sink.send(&batch(1));
+        sink.flush();
+        assert_eq!(*script.attempts.borrow(), 1);
+
+        // The backoff's first delay is between 20 and 40ms
+        let due = sink.next_due().unwrap();
+        assert!(due >= Instant::now() + Duration::from_millis(10));
+        sink.flush();
+        assert_eq!(*script.attempts.borrow(), 1);
+
+        // The failed batch is kept and written once the delay has passed
+        *script.failing.borrow_mut() = false;
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        sink.flush();
+        assert_eq!(*script.attempts.borrow(), 2);
+        assert_eq!(*script.writes.borrow(), vec![vec![1]]);
+        assert_eq!(sink.delivery.backoff.attempts(), 0);
+    }
+}
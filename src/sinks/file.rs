@@ -0,0 +1,83 @@
+//! Appends batches to a local file as JSON lines.
+
+use super::Output;
+use crate::metrics::Batch;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+pub struct FileOutput {
+    path: PathBuf,
+    /// Opened on the first write, and again after a failed one in case the
+    /// file was moved or its disk replaced.
+    file: Option<File>,
+}
+
+impl FileOutput {
+    pub fn new(path: PathBuf) -> Self {
+        FileOutput { path, file: None }
+    }
+}
+
+impl Output for FileOutput {
+    fn write(&mut self, batches: &[Batch]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for batch in batches {
+            serde_json::to_writer(&mut lines, batch)?;
+            lines.push(b'\n');
+        }
+
+        if self.file.is_none() {
+            self.file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        let file = self.file.as_mut().expect("opened above");
+        if let Err(e) = file.write_all(&lines).and_then(|_| file.flush()) {
+            self.file = None;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn appends_one_json_line_per_batch() {
+        let path = std::env::temp_dir().join(format!(
+            "wasi-metrics-file-sink-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let mut output = FileOutput::new(path.clone());
+        output.write(&[Batch::new("edge-01", 1, 1)]).unwrap();
+        output
+            .write(&[Batch::new("edge-01", 1, 2), Batch::new("edge-01", 1, 3)])
+            .unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let seqs: Vec<u64> = text
+            .lines()
+            .map(|line| serde_json::from_str::<Batch>(line).unwrap().seq)
+            .collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fails_when_the_file_cannot_be_opened() {
+        let path = std::env::temp_dir()
+            .join(format!("wasi-metrics-missing-{}", std::process::id()))
+            .join("metrics.jsonl");
+        let mut output = FileOutput::new(path);
+        assert!(output.write(&[Batch::new("edge-01", 1, 1)]).is_err());
+        assert!(output.file.is_none());
+    }
+}
@@ -0,0 +1,198 @@
+//! POSTs batches to an HTTP endpoint as a JSON array.
+//!
+//! Speaks just enough HTTP/1.1 over a plain `TcpStream` to need no HTTP
+//! client, with one connection per request.
+
+use super::Output;
+use crate::metrics::Batch;
+use crate::upstream;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+pub struct HttpOutput {
+    /// `host:port`, as sent in the `Host` header and connected to.
+    authority: String,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpOutput {
+    /// Parses `url`, which must look like `http://host[:port][/path]`.
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("{:?} is not an http:// URL", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("{:?} has no host", url));
+        }
+        // A port follows the host, and an IPv6 host is bracketed because its
+        // address has colons of its own
+        let host_end = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.find(']') {
+                Some(i) => i + 2,
+                None => return Err(format!("{:?} has an unclosed [ in its host", url)),
+            },
+            None => 0,
+        };
+        let authority = if authority[host_end..].contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Ok(HttpOutput {
+            authority,
+            path: path.to_string(),
+            timeout,
+        })
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let stream = upstream::connect_timeout(self.authority.as_str(), self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(stream)
+    }
+}
+
+impl Output for HttpOutput {
+    fn write(&mut self, batches: &[Batch]) -> io::Result<()> {
+        let body = serde_json::to_vec(batches)?;
+        let mut stream = self.connect()?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.authority,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        // Only the status matters; the rest of the response is ignored
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed status line {:?}", status_line.trim_end()),
+                )
+            })?;
+        if !(200..300).contains(&status) {
+            return Err(io::Error::other(format!(
+                "endpoint answered {}",
+                status_line.trim_end()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn parse(url: &str) -> (String, String) {
+        let output = HttpOutput::new(url, Duration::from_secs(1)).unwrap();
+        (output.authority, output.path)
+    }
+
+    #[test]
+    fn parses_host_port_and_path() {
+        assert_eq!(
+            parse("http://collector:9000/ingest"),
+            ("collector:9000".into(), "/ingest".into())
+        );
+        assert_eq!(
+            parse("http://collector/a/b?x=1"),
+            ("collector:80".into(), "/a/b?x=1".into())
+        );
+        assert_eq!(parse("http://10.0.0.5"), ("10.0.0.5:80".into(), "/".into()));
+    }
+
+    #[test]
+    fn parses_bracketed_ipv6_hosts() {
+        assert_eq!(parse("http://[::1]/x"), ("[::1]:80".into(), "/x".into()));
+        assert_eq!(
+            parse("http://[::1]:9000/x"),
+            ("[::1]:9000".into(), "/x".into())
+        );
+        assert_eq!(
+            parse("http://[fe80::1]"),
+            ("[fe80::1]:80".into(), "/".into())
+        );
+        assert!(HttpOutput::new("http://[::1/x", Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn rejects_other_urls() {
+        assert!(HttpOutput::new("https://collector/ingest", Duration::from_secs(1)).is_err());
+        assert!(HttpOutput::new("collector:9000", Duration::from_secs(1)).is_err());
+        assert!(HttpOutput::new("http:///ingest", Duration::from_secs(1)).is_err());
+    }
+
+    /// Answers one request with `status_line` and returns what it received.
+    fn serve_once(status_line: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(std::str::from_utf8(&body).unwrap());
+
+            let mut stream = reader.into_inner();
+            write!(stream, "{}\r\nContent-Length: 0\r\n\r\n", status_line).unwrap();
+            request
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn posts_batches_as_a_json_array() {
+        let (url, server) = serve_once("HTTP/1.1 204 No Content");
+        let mut output = HttpOutput::new(&url, Duration::from_secs(5)).unwrap();
+        let batches = [Batch::new("edge-01", 1, 1), Batch::new("edge-01", 1, 2)];
+        output.write(&batches).unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /ingest HTTP/1.1\r\n"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        let sent: Vec<Batch> = serde_json::from_str(body).unwrap();
+        assert_eq!(sent.iter().map(|b| b.seq).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn fails_on_an_error_status() {
+        let (url, server) = serve_once("HTTP/1.1 503 Service Unavailable");
+        let mut output = HttpOutput::new(&url, Duration::from_secs(5)).unwrap();
+        let e = output.write(&[Batch::new("edge-01", 1, 1)]).unwrap_err();
+        assert!(e.to_string().contains("503"));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,416 @@
+//! Where the agent's batches go.
+//!
+//! An agent can feed several sinks at once: the native server, a file of
+//! JSON lines, stdout and an HTTP endpoint. Each sink queues batches on its
+//! own and retries on its own schedule, so one that is down or slow to come
+//! back does not hold up or lose data for the others.
+//!
+//! The file and stdout sinks deliver on the agent's thread: [`Fanout::flush`]
+//! gives every sink a chance to deliver, and [`Fanout::next_due`] says when
+//! to call it again. The server and http sinks wait on the network, for up to
+//! their timeout per attempt, so each runs on a thread of its own and only
+//! takes batches from the agent's thread.
+
+use crate::backoff::Backoff;
+use crate::config::{ClientConfig, ConfigError};
+use crate::metrics::Batch;
+use serde::Deserialize;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+mod buffered;
+pub mod file;
+pub mod http;
+pub mod server;
+pub mod stdout;
+mod threaded;
+
+pub use buffered::{Buffered, Delivery, Output};
+pub use threaded::Threaded;
+
+/// Names of the sinks an agent can be configured with.
+pub const SINKS: &[&str] = &["server", "file", "stdout", "http"];
+
+/// Sinks that open connections, and so run on their own thread. WASI preview
+/// 1 cannot open any: `std`'s `TcpStream::connect` fails there with
+/// "operation not supported".
+const NETWORK_SINKS: &[&str] = &["server", "http"];
+
+/// A destination for batches.
+pub trait Sink {
+    /// Identifies the sink in logs, e.g. `server` or `http`.
+    fn name(&self) -> &str;
+
+    /// Queues `batch` for delivery. Never blocks on the destination.
+    fn send(&mut self, batch: &Batch);
+
+    /// Delivers what is queued and due, as far as the destination and the
+    /// sink's retry policy allow. Failures are logged and retried later.
+    fn flush(&mut self);
+
+    /// When [`flush`](Sink::flush) has something to do next, or `None` if
+    /// nothing is queued.
+    fn next_due(&self) -> Option<Instant>;
+}
+
+/// Sends every batch to each of several sinks.
+#[derive(Default)]
+pub struct Fanout {
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl Fanout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Names of the sinks, in the order they were added.
+    pub fn names(&self) -> Vec<&str> {
+        self.sinks.iter().map(|s| s.name()).collect()
+    }
+
+    pub fn send(&mut self, batch: &Batch) {
+        for sink in &mut self.sinks {
+            sink.send(batch);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for sink in &mut self.sinks {
+            sink.flush();
+        }
+    }
+
+    /// The earliest time any sink has something to do.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.sinks.iter().filter_map(|s| s.next_due()).min()
+    }
+}
+
+/// Opens every sink `config` enables.
+pub fn open(config: &ClientConfig, host_id: &str) -> io::Result<Fanout> {
+    let sinks = &config.sinks;
+    let mut fanout = Fanout::new();
+    for name in &sinks.enabled {
+        let sink: Box<dyn Sink + Send> = match name.as_str() {
+            "server" => Box::new(server::ServerSink::open(config, host_id)?),
+            "file" => Box::new(Buffered::new(
+                "file",
+                file::FileOutput::new(sinks.file.path.clone()),
+                sinks.file.delivery(),
+            )),
+            "stdout" => Box::new(Buffered::new(
+                "stdout",
+                stdout::StdoutOutput,
+                sinks.stdout.delivery(),
+            )),
+            "http" => Box::new(Buffered::new(
+                "http",
+                http::HttpOutput::new(
+                    &sinks.http.url,
+                    Duration::from_secs(sinks.http.timeout_secs),
+                )
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+                sinks.http.delivery(),
+            )),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown sink {:?}", other),
+                ))
+            }
+        };
+        if NETWORK_SINKS.contains(&name.as_str()) {
+            fanout.add(Box::new(Threaded::spawn(sink)?));
+        } else {
+            fanout.add(sink);
+        }
+    }
+    Ok(fanout)
+}
+
+/// The `[sinks]` section of the agent config. The server sink is set up by
+/// the `[upstream]` and `[spool]` sections.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Sinks every batch is sent to.
+    pub enabled: Vec<String>,
+    pub file: FileSinkConfig,
+    pub stdout: StdoutSinkConfig,
+    pub http: HttpSinkConfig,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        // A WASI agent cannot reach the server, so it prints its batches for
+        // the host to forward
+        let default = if cfg!(target_os = "wasi") {
+            "stdout"
+        } else {
+            "server"
+        };
+        SinksConfig {
+            enabled: vec![default.to_string()],
+            file: FileSinkConfig::default(),
+            stdout: StdoutSinkConfig::default(),
+            http: HttpSinkConfig::default(),
+        }
+    }
+}
+
+impl SinksConfig {
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.iter().any(|n| n == name)
+    }
+
+    /// Checks the settings of every enabled sink.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled.is_empty() {
+            return Err(invalid(
+                "sinks",
+                "at least one sink is required".to_string(),
+            ));
+        }
+        if let Some(name) = self.enabled.iter().find(|n| !SINKS.contains(&n.as_str())) {
+            return Err(invalid(
+                "sinks",
+                format!(
+                    "unknown sink {:?}, expected one of {}",
+                    name,
+                    SINKS.join(", ")
+                ),
+            ));
+        }
+        if cfg!(target_os = "wasi") {
+            let network = self
+                .enabled
+                .iter()
+                .find(|n| NETWORK_SINKS.contains(&n.as_str()));
+            if let Some(name) = network {
+                return Err(invalid(
+                    "sinks",
+                    format!(
+                        "the {} sink needs network connections, which WASI does not offer; \
+                         use the file or stdout sink",
+                        name
+                    ),
+                ));
+            }
+        }
+        if self.is_enabled("http") {
+            http::HttpOutput::new(&self.http.url, Duration::from_secs(1))
+                .map_err(|reason| invalid("sinks.http.url", reason))?;
+            if self.http.timeout_secs == 0 {
+                return Err(invalid(
+                    "sinks.http.timeout_secs",
+                    "must be at least one second".to_string(),
+                ));
+            }
+        }
+        let batch_sizes = [
+            ("file", self.file.batch_size),
+            ("stdout", self.stdout.batch_size),
+            ("http", self.http.batch_size),
+        ];
+        for (name, batch_size) in batch_sizes {
+            if batch_size == 0 && self.is_enabled(name) {
+                return Err(invalid(
+                    &format!("sinks.{}.batch_size", name),
+                    "must be at least 1".to_string(),
+                ));
+            }
+        }
+        // With no room in the queue every batch would be dropped on arrival
+        let queue_limits = [
+            ("file", self.file.max_queued),
+            ("http", self.http.max_queued),
+        ];
+        for (name, max_queued) in queue_limits {
+            if max_queued == 0 && self.is_enabled(name) {
+                return Err(invalid(
+                    &format!("sinks.{}.max_queued", name),
+                    "must be at least 1".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(key: &str, reason: String) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        reason,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileSinkConfig {
+    /// File the batches are appended to, one JSON object per line.
+    pub path: PathBuf,
+    /// Batches written together.
+    pub batch_size: usize,
+    /// Longest a batch waits for others to fill a write.
+    pub linger_secs: u64,
+    /// Batches kept while the file cannot be written; the oldest go first.
+    pub max_queued: usize,
+    pub retry_initial_ms: u64,
+    pub retry_max_secs: u64,
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        FileSinkConfig {
+            path: PathBuf::from("metrics.jsonl"),
+            batch_size: 10,
+            linger_secs: 30,
+            max_queued: 1000,
+            retry_initial_ms: 500,
+            retry_max_secs: 60,
+        }
+    }
+}
+
+impl FileSinkConfig {
+    pub fn delivery(&self) -> Delivery {
+        Delivery {
+            batch_size: self.batch_size,
+            linger: Duration::from_secs(self.linger_secs),
+            max_queued: self.max_queued,
+            backoff: Backoff::new(
+                Duration::from_millis(self.retry_initial_ms),
+                Duration::from_secs(self.retry_max_secs),
+            ),
+        }
+    }
+}
+
+/// Batches printed as JSON lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StdoutSinkConfig {
+    pub batch_size: usize,
+    pub linger_secs: u64,
+}
+
+impl Default for StdoutSinkConfig {
+    fn default() -> Self {
+        StdoutSinkConfig {
+            batch_size: 1,
+            linger_secs: 0,
+        }
+    }
+}
+
+impl StdoutSinkConfig {
+    pub fn delivery(&self) -> Delivery {
+        Delivery {
+            batch_size: self.batch_size,
+            linger: Duration::from_secs(self.linger_secs),
+            max_queued: self.batch_size.max(100),
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(10)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSinkConfig {
+    /// Endpoint the batches are POSTed to as a JSON array, e.g.
+    /// `http://collector:9000/ingest`. Only plain HTTP is supported.
+    pub url: String,
+    /// Seconds to wait when connecting and for the response.
+    pub timeout_secs: u64,
+    pub batch_size: usize,
+    pub linger_secs: u64,
+    pub max_queued: usize,
+    pub retry_initial_ms: u64,
+    pub retry_max_secs: u64,
+}
+
+impl Default for HttpSinkConfig {
+    fn default() -> Self {
+        HttpSinkConfig {
+            url: String::new(),
+            timeout_secs: 10,
+            batch_size: 10,
+            linger_secs: 10,
+            max_queued: 1000,
+            retry_initial_ms: 500,
+            retry_max_secs: 60,
+        }
+    }
+}
+
+impl HttpSinkConfig {
+    pub fn delivery(&self) -> Delivery {
+        Delivery {
+            batch_size: self.batch_size,
+            linger: Duration::from_secs(self.linger_secs),
+            max_queued: self.max_queued,
+            backoff: Backoff::new(
+                Duration::from_millis(self.retry_initial_ms),
+                Duration::from_secs(self.retry_max_secs),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled(names: &[&str]) -> SinksConfig {
+        SinksConfig {
+            enabled: names.iter().map(|n| n.to_string()).collect(),
+            ..SinksConfig::default()
+        }
+    }
+
+    fn rejected_key(config: &SinksConfig) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected an invalid key, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        enabled(&["file", "stdout"]).validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_or_no_sinks() {
+        assert_eq!(rejected_key(&enabled(&[])), "sinks");
+        assert_eq!(rejected_key(&enabled(&["stdout", "syslog"])), "sinks");
+    }
+
+    #[test]
+    fn rejects_an_empty_queue() {
+        let mut config = enabled(&["file"]);
+        config.file.max_queued = 0;
+        assert_eq!(rejected_key(&config), "sinks.file.max_queued");
+
+        // Only the enabled sinks are checked
+        config.enabled = vec!["stdout".to_string()];
+        config.validate().unwrap();
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    #[test]
+    fn checks_the_http_url() {
+        let mut config = enabled(&["http"]);
+        assert_eq!(rejected_key(&config), "sinks.http.url");
+        config.http.url = "http://[::1]:9000/ingest".to_string();
+        config.validate().unwrap();
+        config.http.max_queued = 0;
+        assert_eq!(rejected_key(&config), "sinks.http.max_queued");
+    }
+}
@@ -0,0 +1,68 @@
+//! Delivers batches to the native server over the binary protocol.
+
+use super::Sink;
+use crate::config::ClientConfig;
+use crate::metrics::Batch;
+use crate::spool::Spool;
+use crate::upstream::Upstream;
+use std::io;
+use std::time::Instant;
+
+/// Batches wait in the on-disk [`Spool`] until the server acknowledges them,
+/// so they survive both network outages and agent restarts; the
+/// [`Upstream`] connection handles failover and reconnect backoff.
+pub struct ServerSink {
+    spool: Spool,
+    upstream: Upstream,
+}
+
+impl ServerSink {
+    pub fn open(config: &ClientConfig, host_id: &str) -> io::Result<Self> {
+        let spool = Spool::open(config.spool_config())?;
+        if !spool.is_empty() {
+            eprintln!("Replaying {} spooled batches", spool.len());
+        }
+
+        // One connection is kept open and reused for every batch
+        let upstream = Upstream::new(
+            host_id,
+            config.upstream.servers.clone(),
+            config.upstream_timeout(),
+            config.backoff(),
+        );
+        Ok(ServerSink { spool, upstream })
+    }
+}
+
+impl Sink for ServerSink {
+    fn name(&self) -> &str {
+        "server"
+    }
+
+    fn send(&mut self, batch: &Batch) {
+        let dropped = self.spool.dropped();
+        if let Err(e) = self.spool.push(batch) {
+            eprintln!("Failed to spool batch {}: {}", batch.seq, e);
+        }
+        if self.spool.dropped() > dropped {
+            eprintln!(
+                "Spool is full, dropped {} batches",
+                self.spool.dropped() - dropped
+            );
+        }
+    }
+
+    fn flush(&mut self) {
+        self.upstream.flush(&mut self.spool);
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        // Each flush sends one window, so while connected the next is due
+        // right away
+        if self.spool.is_empty() {
+            None
+        } else {
+            Some(self.upstream.next_attempt())
+        }
+    }
+}
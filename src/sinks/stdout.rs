@@ -0,0 +1,19 @@
+//! Prints batches to stdout as JSON lines.
+
+use super::Output;
+use crate::metrics::Batch;
+use std::io::{self, Write};
+
+pub struct StdoutOutput;
+
+impl Output for StdoutOutput {
+    fn write(&mut self, batches: &[Batch]) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for batch in batches {
+            serde_json::to_writer(&mut out, batch)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,172 @@
+//! Runs a sink that talks to the network on a thread of its own.
+
+use super::Sink;
+use crate::metrics::Batch;
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::Instant;
+
+/// Batches handed to a sink's thread that it has not picked up yet. The
+/// thread takes them between deliveries, so this only fills up while a single
+/// delivery attempt is stuck on a slow destination.
+const HANDOFF_CAPACITY: usize = 256;
+
+/// A sink whose deliveries run on their own thread, so that a destination
+/// that is slow to connect or to reply holds up neither collection nor the
+/// other sinks.
+///
+/// [`send`](Sink::send) hands the batch over without blocking; the thread
+/// queues it in the inner sink and flushes that whenever it is due.
+pub struct Threaded {
+    name: String,
+    handoff: SyncSender<Batch>,
+    dropped: u64,
+}
+
+impl Threaded {
+    pub fn spawn(sink: Box<dyn Sink + Send>) -> io::Result<Self> {
+        let name = sink.name().to_string();
+        let (handoff, batches) = mpsc::sync_channel(HANDOFF_CAPACITY);
+        thread::Builder::new()
+            .name(format!("sink-{}", name))
+            .spawn(move || deliver(sink, batches))?;
+        Ok(Threaded {
+            name,
+            handoff,
+            dropped: 0,
+        })
+    }
+}
+
+impl Sink for Threaded {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, batch: &Batch) {
+        match self.handoff.try_send(batch.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                eprintln!(
+                    "The {} sink is not keeping up, dropped {} batches so far",
+                    self.name, self.dropped
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                eprintln!(
+                    "The {} sink has stopped, dropped batch {}",
+                    self.name, batch.seq
+                );
+            }
+        }
+    }
+
+    /// Deliveries happen on the sink's thread.
+    fn flush(&mut self) {}
+
+    fn next_due(&self) -> Option<Instant> {
+        None
+    }
+}
+
+/// The sink's thread: queues every batch handed over and flushes the sink
+/// whenever it is due, until the [`Threaded`] end goes away.
+fn deliver(mut sink: Box<dyn Sink + Send>, batches: Receiver<Batch>) {
+    loop {
+        let received = match sink.next_due() {
+            Some(due) => batches.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => batches.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(batch) => sink.send(&batch),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        // Queue everything that arrived meanwhile before delivering
+        while let Ok(batch) = batches.try_recv() {
+            sink.send(&batch);
+        }
+        sink.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backoff::Backoff;
+    use crate::sinks::http::HttpOutput;
+    use crate::sinks::{Buffered, Delivery, Fanout, Output};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Remembers the sequence numbers of the batches written to it.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<u64>>>);
+
+    impl Output for Recorder {
+        fn write(&mut self, batches: &[Batch]) -> io::Result<()> {
+            self.0.lock().unwrap().extend(batches.iter().map(|b| b.seq));
+            Ok(())
+        }
+    }
+
+    fn delivery() -> Delivery {
+        Delivery {
+            batch_size: 1,
+            linger: Duration::ZERO,
+            max_queued: 100,
+            backoff: Backoff::new(Duration::from_millis(10), Duration::from_millis(100)),
+        }
+    }
+
+    #[test]
+    fn a_stalled_destination_does_not_hold_up_other_sinks() {
+        // Accepts connections (into its backlog) but never replies
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let http = HttpOutput::new(&url, Duration::from_secs(5)).unwrap();
+
+        let recorder = Recorder::default();
+        let mut fanout = Fanout::new();
+        fanout.add(Box::new(
+            Threaded::spawn(Box::new(Buffered::new("http", http, delivery()))).unwrap(),
+        ));
+        fanout.add(Box::new(Buffered::new(
+            "record",
+            recorder.clone(),
+            delivery(),
+        )));
+
+        let started = Instant::now();
+        for seq in 1..=5 {
+            fanout.send(&Batch::new("edge-01", 1, seq));
+            fanout.flush();
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(*recorder.0.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn delivers_on_the_sink_thread() {
+        let recorder = Recorder::default();
+        let mut sink = Threaded::spawn(Box::new(Buffered::new(
+            "record",
+            recorder.clone(),
+            delivery(),
+        )))
+        .unwrap();
+        assert_eq!(sink.name(), "record");
+        sink.send(&Batch::new("edge-01", 1, 1));
+        sink.send(&Batch::new("edge-01", 1, 2));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while recorder.0.lock().unwrap().len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*recorder.0.lock().unwrap(), vec![1, 2]);
+        assert_eq!(sink.next_due(), None);
+    }
+}
@@ -0,0 +1,606 @@
+//! A bounded on-disk queue of batches waiting to be delivered.
+//!
+//! Batches are appended to segment files (`<id>.seg`) in the spool directory
+//! as length-prefixed JSON records. A `cursor` file remembers the position of
+//! the oldest unacknowledged record, so a restarted agent picks up exactly
+//! where it left off. Segments are deleted once every record in them has
+//! been acknowledged.
+
+use crate::metrics::Batch;
+use serde::Deserialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const SEGMENT_EXT: &str = "seg";
+const CURSOR_FILE: &str = "cursor";
+
+/// Size of the length prefix in front of every record.
+const RECORD_HEADER_LEN: u64 = 4;
+
+/// What to do when a new batch would push the spool over its size cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    /// Discard the oldest queued batches to make room.
+    DropOldest,
+    /// Keep what is queued and discard the new batch.
+    DropNewest,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(DropPolicy::DropOldest),
+            "drop_newest" => Ok(DropPolicy::DropNewest),
+            other => Err(format!(
+                "unknown drop policy {:?}, expected drop_oldest or drop_newest",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    /// Upper bound on the size of queued records. Disk usage can exceed it
+    /// by at most one segment while a partly acknowledged segment is kept.
+    pub max_bytes: u64,
+    /// A new segment file is started once the current one reaches this size.
+    pub segment_bytes: u64,
+    pub drop_policy: DropPolicy,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        SpoolConfig {
+            dir: PathBuf::from("metrics_spool"),
+            max_bytes: 64 * 1024 * 1024,
+            segment_bytes: 4 * 1024 * 1024,
+            drop_policy: DropPolicy::DropOldest,
+        }
+    }
+}
+
+/// Where a queued record lives on disk.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    segment: u64,
+    offset: u64,
+    len: u32,
+}
+
+impl Entry {
+    fn size(&self) -> u64 {
+        RECORD_HEADER_LEN + self.len as u64
+    }
+}
+
+/// The segment currently being appended to.
+struct Writer {
+    segment: u64,
+    file: File,
+    size: u64,
+}
+
+pub struct Spool {
+    config: SpoolConfig,
+    entries: VecDeque<Entry>,
+    segments: BTreeSet<u64>,
+    queued_bytes: u64,
+    writer: Option<Writer>,
+    /// Id given to the next segment; never reused, so the cursor can't
+    /// point at a newer segment with the same id.
+    next_segment: u64,
+    dropped: u64,
+}
+
+impl Spool {
+    /// Opens the spool in `config.dir`, creating it if needed and recovering
+    /// any batches left over from a previous run.
+    pub fn open(config: SpoolConfig) -> io::Result<Spool> {
+        fs::create_dir_all(&config.dir)?;
+
+        let mut spool = Spool {
+            config,
+            entries: VecDeque::new(),
+            segments: BTreeSet::new(),
+            queued_bytes: 0,
+            writer: None,
+            next_segment: 0,
+            dropped: 0,
+        };
+
+        let segments = list_segments(&spool.config.dir)?;
+        let (cursor_segment, cursor_offset) = match spool.read_cursor()? {
+            Some(cursor) => cursor,
+            // Replaying acknowledged batches is better than losing the
+            // rest; the server drops what it has already stored
+            None => (segments.first().copied().unwrap_or(0), 0),
+        };
+        spool.next_segment = cursor_segment + 1;
+        for segment in segments {
+            if segment < cursor_segment {
+                // Fully acknowledged before we were stopped
+                fs::remove_file(spool.segment_path(segment))?;
+                continue;
+            }
+            let start = if segment == cursor_segment {
+                cursor_offset
+            } else {
+                0
+            };
+            spool.recover_segment(segment, start)?;
+            spool.segments.insert(segment);
+            spool.next_segment = spool.next_segment.max(segment + 1);
+        }
+
+        Ok(spool)
+    }
+
+    /// Number of batches waiting to be delivered.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the queued records in bytes.
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued_bytes
+    }
+
+    /// Number of batches discarded because of the size cap or corruption
+    /// since the spool was opened.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Appends `batch` to the back of the queue, applying the drop policy if
+    /// the spool is full.
+    pub fn push(&mut self, batch: &Batch) -> io::Result<()> {
+        let payload = serde_json::to_vec(batch)?;
+        let record_size = RECORD_HEADER_LEN + payload.len() as u64;
+        if record_size > self.config.max_bytes {
+            // It would never fit, and making room would empty the spool
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "a record of {} bytes exceeds the spool cap of {} bytes",
+                    record_size, self.config.max_bytes
+                ),
+            ));
+        }
+
+        if self.queued_bytes + record_size > self.config.max_bytes {
+            match self.config.drop_policy {
+                DropPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return Ok(());
+                }
+                DropPolicy::DropOldest => {
+                    while !self.entries.is_empty()
+                        && self.queued_bytes + record_size > self.config.max_bytes
+                    {
+                        self.pop()?;
+                        self.dropped += 1;
+                    }
+                }
+            }
+        }
+
+        let writer = self.writer()?;
+        let offset = writer.size;
+        writer
+            .file
+            .write_all(&(payload.len() as u32).to_be_bytes())?;
+        writer.file.write_all(&payload)?;
+        writer.file.sync_data()?;
+        writer.size += record_size;
+
+        let entry = Entry {
+            segment: writer.segment,
+            offset,
+            len: payload.len() as u32,
+        };
+        self.queued_bytes += entry.size();
+        self.entries.push_back(entry);
+        Ok(())
+    }
+
+    /// Returns the oldest queued batch without removing it.
+    ///
+    /// Records that can no longer be decoded are discarded and counted as
+    /// dropped rather than blocking the queue forever.
+    pub fn front(&mut self) -> io::Result<Option<Batch>> {
+        while let Some(entry) = self.entries.front().copied() {
+            let payload = self.read_record(entry)?;
+            match serde_json::from_slice(&payload) {
+                Ok(batch) => return Ok(Some(batch)),
+                Err(e) => {
+                    eprintln!(
+                        "Discarding unreadable spooled batch in segment {}: {}",
+                        entry.segment, e
+                    );
+                    self.pop()?;
+                    self.dropped += 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns up to `max` of the oldest queued batches without removing
+    /// them, oldest first.
+    ///
+    /// Stops early at a record that can't be decoded; it is discarded once it
+    /// reaches the front of the queue.
+    pub fn peek(&mut self, max: usize) -> io::Result<Vec<Batch>> {
+        let mut batches = Vec::new();
+        if self.front()?.is_none() {
+            return Ok(batches);
+        }
+        for entry in self.entries.iter().take(max) {
+            let payload = self.read_record(*entry)?;
+            match serde_json::from_slice(&payload) {
+                Ok(batch) => batches.push(batch),
+                Err(_) => break,
+            }
+        }
+        Ok(batches)
+    }
+
+    /// Removes the oldest queued batch, typically once it has been
+    /// acknowledged.
+    pub fn pop(&mut self) -> io::Result<()> {
+        let entry = match self.entries.pop_front() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        self.queued_bytes -= entry.size();
+        self.write_cursor(entry.segment, entry.offset + entry.size())?;
+        self.remove_consumed_segments()
+    }
+
+    fn writer(&mut self) -> io::Result<&mut Writer> {
+        let rotate = match &self.writer {
+            Some(writer) => writer.size >= self.config.segment_bytes,
+            None => true,
+        };
+        if rotate {
+            // Always start a fresh segment rather than appending to one
+            // recovered from disk
+            let segment = self.next_segment;
+            self.next_segment += 1;
+            let file = OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(self.segment_path(segment))?;
+            self.segments.insert(segment);
+            self.writer = Some(Writer {
+                segment,
+                file,
+                size: 0,
+            });
+            self.remove_consumed_segments()?;
+        }
+        Ok(self.writer.as_mut().expect("writer was just opened"))
+    }
+
+    /// Deletes segments that come before the oldest queued record and are
+    /// not being written to.
+    fn remove_consumed_segments(&mut self) -> io::Result<()> {
+        let active = self.writer.as_ref().map(|w| w.segment);
+        let keep_from = match self.entries.front() {
+            Some(entry) => entry.segment,
+            None => active.unwrap_or(u64::MAX),
+        };
+
+        let consumed: Vec<u64> = self
+            .segments
+            .iter()
+            .copied()
+            .filter(|&s| s < keep_from && Some(s) != active)
+            .collect();
+        for segment in consumed {
+            fs::remove_file(self.segment_path(segment))?;
+            self.segments.remove(&segment);
+        }
+        Ok(())
+    }
+
+    /// Indexes the records of `segment` from `start`, truncating a record
+    /// that was only partly written when the agent stopped.
+    fn recover_segment(&mut self, segment: u64, start: u64) -> io::Result<()> {
+        let path = self.segment_path(segment);
+        let mut data = Vec::new();
+        File::open(&path)?.read_to_end(&mut data)?;
+
+        let mut offset = start as usize;
+        if offset > data.len() {
+            eprintln!(
+                "Spool cursor is past the end of segment {}, replaying all of it",
+                segment
+            );
+            offset = 0;
+        }
+        while offset + RECORD_HEADER_LEN as usize <= data.len() {
+            let len = u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ]);
+            let end = offset + RECORD_HEADER_LEN as usize + len as usize;
+            if end > data.len() {
+                break;
+            }
+            let entry = Entry {
+                segment,
+                offset: offset as u64,
+                len,
+            };
+            self.queued_bytes += entry.size();
+            self.entries.push_back(entry);
+            offset = end;
+        }
+
+        if offset < data.len() {
+            eprintln!(
+                "Truncating {} torn bytes at the end of spool segment {}",
+                data.len() - offset,
+                segment
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(offset as u64)?;
+        }
+        Ok(())
+    }
+
+    fn read_record(&self, entry: Entry) -> io::Result<Vec<u8>> {
+        let mut file = File::open(self.segment_path(entry.segment))?;
+        file.seek(SeekFrom::Start(entry.offset + RECORD_HEADER_LEN))?;
+        let mut payload = vec![0u8; entry.len as usize];
+        file.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+
+    /// Reads the position of the oldest unacknowledged record, or `None` if
+    /// there is no cursor or it cannot be parsed.
+    fn read_cursor(&self) -> io::Result<Option<(u64, u64)>> {
+        let bytes = match fs::read(self.config.dir.join(CURSOR_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let text = String::from_utf8_lossy(&bytes);
+        let mut parts = text.split_whitespace().map(str::parse::<u64>);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(segment)), Some(Ok(offset)), None) => Ok(Some((segment, offset))),
+            _ => {
+                eprintln!(
+                    "Ignoring invalid spool cursor {:?}, replaying from the oldest segment",
+                    text.trim()
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Persists the cursor by writing a temporary file and renaming it over
+    /// the old one, so a crash never leaves a half-written cursor behind.
+    fn write_cursor(&self, segment: u64, offset: u64) -> io::Result<()> {
+        let tmp = self.config.dir.join(format!("{}.tmp", CURSOR_FILE));
+        let mut file = File::create(&tmp)?;
+        writeln!(file, "{} {}", segment, offset)?;
+        // The rename may reach the disk before the data otherwise
+        file.sync_all()?;
+        fs::rename(tmp, self.config.dir.join(CURSOR_FILE))
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        segment_path(&self.config.dir, segment)
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", segment, SEGMENT_EXT))
+}
+
+/// Lists the ids of the segment files in `dir`, oldest first.
+fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXT) {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            segments.push(id);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty spool directory of its own for each test.
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "wasi_metrics-spool-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(dir: &Path, max_bytes: u64, drop_policy: DropPolicy) -> SpoolConfig {
+        SpoolConfig {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            segment_bytes: 256,
+            drop_policy,
+        }
+    }
+
+    fn batch(seq: u64) -> Batch {
+        Batch::new("edge-01", 1, seq)
+    }
+
+    fn record_size(seq: u64) -> u64 {
+        RECORD_HEADER_LEN + serde_json::to_vec(&batch(seq)).unwrap().len() as u64
+    }
+
+    fn seqs(spool: &mut Spool) -> Vec<u64> {
+        spool.peek(usize::MAX).unwrap().iter().map(|b| b.seq).collect()
+    }
+
+    #[test]
+    fn replays_unacknowledged_batches_after_a_restart() {
+        let dir = dir("replay");
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        for seq in 1..=10 {
+            spool.push(&batch(seq)).unwrap();
+        }
+        spool.pop().unwrap();
+        spool.pop().unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        assert_eq!(seqs(&mut spool), (3..=10).collect::<Vec<_>>());
+        assert_eq!(spool.queued_bytes(), (3..=10).map(record_size).sum::<u64>());
+
+        // New batches go after the recovered ones
+        spool.push(&batch(11)).unwrap();
+        while spool.len() > 1 {
+            spool.pop().unwrap();
+        }
+        assert_eq!(seqs(&mut spool), vec![11]);
+        drop(spool);
+
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        assert_eq!(seqs(&mut spool), vec![11]);
+        // Only the segment holding batch 11 is left
+        assert_eq!(list_segments(&dir).unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drop_oldest_makes_room() {
+        let dir = dir("drop-oldest");
+        let cap = 3 * record_size(1);
+        let mut spool = Spool::open(config(&dir, cap, DropPolicy::DropOldest)).unwrap();
+        for seq in 1..=5 {
+            spool.push(&batch(seq)).unwrap();
+        }
+        assert_eq!(seqs(&mut spool), vec![3, 4, 5]);
+        assert_eq!(spool.dropped(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drop_newest_keeps_what_is_queued() {
+        let dir = dir("drop-newest");
+        let cap = 3 * record_size(1);
+        let mut spool = Spool::open(config(&dir, cap, DropPolicy::DropNewest)).unwrap();
+        for seq in 1..=5 {
+            spool.push(&batch(seq)).unwrap();
+        }
+        assert_eq!(seqs(&mut spool), vec![1, 2, 3]);
+        assert_eq!(spool.dropped(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_records_larger_than_the_cap() {
+        let dir = dir("oversize");
+        let cap = 2 * record_size(1);
+        let mut spool = Spool::open(config(&dir, cap, DropPolicy::DropOldest)).unwrap();
+        spool.push(&batch(1)).unwrap();
+
+        let mut big = batch(2);
+        big.host_id = "x".repeat(cap as usize);
+        let e = spool.push(&big).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(seqs(&mut spool), vec![1], "nothing was evicted for it");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncates_a_torn_record() {
+        let dir = dir("torn");
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        spool.push(&batch(1)).unwrap();
+        spool.push(&batch(2)).unwrap();
+        drop(spool);
+
+        let segment = segment_path(&dir, list_segments(&dir).unwrap()[0]);
+        let len = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        assert_eq!(seqs(&mut spool), vec![1]);
+        assert_eq!(fs::metadata(&segment).unwrap().len(), record_size(1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replays_from_the_oldest_segment_without_a_valid_cursor() {
+        for (name, cursor) in [
+            ("cursor-garbage", &b"\xff\x00 not a cursor"[..]),
+            ("cursor-truncated", &b"3"[..]),
+            ("cursor-empty", &b""[..]),
+        ] {
+            let dir = dir(name);
+            let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+            for seq in 1..=10 {
+                spool.push(&batch(seq)).unwrap();
+            }
+            spool.pop().unwrap();
+            drop(spool);
+            fs::write(dir.join(CURSOR_FILE), cursor).unwrap();
+
+            let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+            assert_eq!(seqs(&mut spool), (1..=10).collect::<Vec<_>>(), "{}", name);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_a_segment_the_cursor_points_past() {
+        let dir = dir("cursor-past-end");
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        spool.push(&batch(1)).unwrap();
+        spool.push(&batch(2)).unwrap();
+        let segment = list_segments(&dir).unwrap()[0];
+        drop(spool);
+        fs::write(dir.join(CURSOR_FILE), format!("{} 100000\n", segment)).unwrap();
+
+        let mut spool = Spool::open(config(&dir, 1 << 20, DropPolicy::DropOldest)).unwrap();
+        assert_eq!(seqs(&mut spool), vec![1, 2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
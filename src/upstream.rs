@@ -0,0 +1,234 @@
+//! The agent's long-lived connection to the server.
+
+use crate::backoff::Backoff;
+use crate::protocol::{self, Hello, Message, ProtocolError};
+use crate::spool::Spool;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Batches sent ahead before waiting for their acknowledgements.
+const SEND_WINDOW: usize = 32;
+
+/// An established, handshaken connection.
+struct Connection {
+    stream: TcpStream,
+    version: u8,
+    server: String,
+}
+
+/// Delivers spooled batches over one persistent connection, failing over
+/// between servers and backing off between reconnect attempts.
+pub struct Upstream {
+    host_id: String,
+    servers: Vec<String>,
+    timeout: Duration,
+    conn: Option<Connection>,
+    backoff: Backoff,
+    next_attempt: Instant,
+}
+
+impl Upstream {
+    /// `servers` are tried in order on every reconnect; `timeout` bounds
+    /// connecting and waiting for each reply.
+    pub fn new(host_id: &str, servers: Vec<String>, timeout: Duration, backoff: Backoff) -> Self {
+        Upstream {
+            host_id: host_id.to_string(),
+            servers,
+            timeout,
+            conn: None,
+            backoff,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// When the next flush is due: right away while connected, or when the
+    /// next reconnect attempt is.
+    pub fn next_attempt(&self) -> Instant {
+        match self.conn {
+            Some(_) => Instant::now(),
+            None => self.next_attempt,
+        }
+    }
+
+    /// Sends the oldest window of spooled batches and removes the acknowledged
+    /// ones, connecting first if needed and the backoff allows it. Call it
+    /// again while [`next_attempt`](Upstream::next_attempt) says so to send
+    /// the rest.
+    ///
+    /// Any failure drops the connection; unacknowledged batches stay in the
+    /// spool and are sent again after reconnecting.
+    pub fn flush(&mut self, spool: &mut Spool) {
+        if spool.is_empty() {
+            return;
+        }
+
+        if self.conn.is_none() {
+            if Instant::now() < self.next_attempt {
+                return;
+            }
+            match self.connect() {
+                Ok(conn) => {
+                    eprintln!("Connected to {}", conn.server);
+                    self.backoff.reset();
+                    self.conn = Some(conn);
+                }
+                Err(e) => {
+                    let delay = self.backoff.next_delay();
+                    self.next_attempt = Instant::now() + delay;
+                    eprintln!(
+                        "Could not connect to any server ({}), retrying in {:.1}s",
+                        e,
+                        delay.as_secs_f64()
+                    );
+                    return;
+                }
+            }
+        }
+
+        let conn = self.conn.as_mut().expect("connected above");
+        if let Err(e) = send_window(conn, spool) {
+            let delay = self.backoff.next_delay();
+            self.next_attempt = Instant::now() + delay;
+            eprintln!(
+                "Lost connection to {} ({}), reconnecting in {:.1}s",
+                conn.server,
+                e,
+                delay.as_secs_f64()
+            );
+            self.conn = None;
+        }
+    }
+
+    /// Tries every server in order and returns the first one that completes
+    /// the handshake.
+    fn connect(&self) -> Result<Connection, ProtocolError> {
+        let mut last_error = None;
+        for server in &self.servers {
+            match self.connect_to(server) {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    eprintln!("Could not connect to server {}: {}", server, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no servers configured").into()
+        }))
+    }
+
+    fn connect_to(&self, server: &str) -> Result<Connection, ProtocolError> {
+        let mut stream = connect_timeout(server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_nodelay(true)?;
+
+        // Introduce ourselves and agree on a protocol version first
+        let hello = Message::Hello(Hello {
+            host_id: self.host_id.clone(),
+            versions: protocol::SUPPORTED_VERSIONS.to_vec(),
+        });
+        protocol::write_message(&mut stream, protocol::PROTOCOL_VERSION, &hello)?;
+
+        let version = match read_reply(&mut stream)? {
+            Message::Welcome(welcome) => welcome.version,
+            Message::Unsupported(unsupported) => {
+                return Err(ProtocolError::Rejected(unsupported));
+            }
+            Message::Busy(busy) => return Err(ProtocolError::Busy(busy)),
+            other => return Err(ProtocolError::Unexpected(other.type_id())),
+        };
+
+        Ok(Connection {
+            stream,
+            version,
+            server: server.to_string(),
+        })
+    }
+}
+
+/// Connects to the first address `server` resolves to that accepts, as a
+/// host name may resolve to both IPv6 and IPv4 addresses of which only one
+/// is reachable. `timeout` applies to each address in turn.
+pub fn connect_timeout<A: ToSocketAddrs>(server: A, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in server.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+    }))
+}
+
+/// Streams the oldest window of the spool: its batches are written back to
+/// back, then the acknowledgements are collected in the same order.
+fn send_window(conn: &mut Connection, spool: &mut Spool) -> Result<(), ProtocolError> {
+    let window = spool.peek(SEND_WINDOW)?;
+    for batch in &window {
+        protocol::write_message(&mut conn.stream, conn.version, &Message::Batch(batch.clone()))?;
+    }
+
+    for batch in &window {
+        match read_reply(&mut conn.stream)? {
+            Message::Ack(ack) if ack.session == batch.session && ack.seq == batch.seq => {
+                spool.pop()?;
+            }
+            Message::Ack(ack) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ack for batch {} while waiting for {}", ack.seq, batch.seq),
+                )
+                .into());
+            }
+            other => return Err(ProtocolError::Unexpected(other.type_id())),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the next message, treating a closed connection as an error.
+fn read_reply(stream: &mut TcpStream) -> Result<Message, ProtocolError> {
+    match protocol::read_message(stream)? {
+        Some(message) => Ok(message),
+        None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "server closed the connection").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, TcpListener};
+
+    /// An address nothing listens on.
+    fn closed_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn tries_every_resolved_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let stream = connect_timeout(&[closed_addr(), open][..], Duration::from_secs(1));
+        assert_eq!(stream.unwrap().peer_addr().unwrap(), open);
+    }
+
+    #[test]
+    fn reports_the_last_failure() {
+        let e = connect_timeout(closed_addr(), Duration::from_secs(1)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+
+        let e = connect_timeout(&[][..] as &[SocketAddr], Duration::from_secs(1)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+}